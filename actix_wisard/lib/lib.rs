@@ -2,14 +2,19 @@ extern crate wisard;
 
 // use actix_multipart::Multipart;
 use actix_web::{
-    dev::BodyEncoding, dev::Decompress, error, guard, http::ContentEncoding, middleware, web, App,
-    Error, HttpResponse, HttpServer,
+    dev::BodyEncoding, dev::Decompress, error, guard, http::header, http::ContentEncoding,
+    http::StatusCode, middleware, web, App, Error, HttpRequest, HttpResponse, HttpServer,
 };
 use async_std::prelude::*;
 use env_logger::Env;
 use futures::StreamExt; //, TryStreamExt};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::sync::RwLock;
+use wisard::config::WisardConfig;
+use wisard::dict_wisard::{BleachPolicy, LoadLimits, Outcome, Score};
+use wisard::errors::WisardError;
+use wisard::format::ExportFormat;
 
 #[actix_web::main]
 pub async fn run() -> std::io::Result<()> {
@@ -25,9 +30,17 @@ pub async fn run() -> std::io::Result<()> {
                 "%a %t %r %b %{Referer}i %{User-Agent}i %s %T",
             ))
             .service(web::resource("/new").route(web::post().to(new)))
+            .service(web::resource("/config").route(web::post().to(config)))
             .service(web::resource("/train?{label}>").route(web::post().to(train)))
             .service(web::resource("/classify").route(web::post().to(classify)))
             .service(web::resource("/info").route(web::get().to(info)))
+            .service(web::resource("/bleach").route(web::post().to(bleach)))
+            .service(web::resource("/weights").route(web::post().to(weights)))
+            .service(
+                web::resource("/tune")
+                    .app_data(web::JsonConfig::default().limit(STREAM_MAX_SIZE))
+                    .route(web::post().to(tune)),
+            )
             .service(
                 web::resource("/model")
                     .route(web::get().to(save))
@@ -56,14 +69,64 @@ async fn new(
             )))
         }
     };
+    // checked like /config, the model keeps its RAM kind
+    let mut config = WisardConfig::new()
+        .without_input_length()
+        .number_of_hashtables(model_info.hashtables)
+        .addr_length(model_info.addresses)
+        .bleach(model_info.bleach)
+        .ram(unlocked_wis.get_ram_kind().clone());
+    if let Some(seed) = model_info.seed {
+        config = config.seed(seed);
+    }
+    if let Err(error) = config.validate() {
+        return Ok(wisard_error(error));
+    }
     unlocked_wis.erase_and_change_hyperparameters(
         model_info.hashtables,
         model_info.addresses,
         model_info.bleach,
+        model_info.seed,
     );
     Ok(HttpResponse::Ok().into())
 }
 
+// replaces the model with the one a WisardConfig describes, as TOML when the
+// Content-Type says so and as JSON otherwise
+async fn config(
+    req: HttpRequest,
+    wis: web::Data<RwLock<wisard::dict_wisard::Wisard<u8>>>,
+    body: String,
+) -> Result<HttpResponse, Error> {
+    let content_type = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok());
+    let is_toml =
+        matches!(content_type, Some(content_type) if content_type.starts_with("application/toml"));
+    let config = if is_toml {
+        WisardConfig::from_toml(&body)
+    } else {
+        WisardConfig::from_json(&body)
+    };
+    let new_wis = match config.and_then(|config| wisard::dict_wisard::Wisard::with_config(&config))
+    {
+        Ok(new_wis) => new_wis,
+        Err(error) => return Ok(wisard_error(error)),
+    };
+
+    let mut unlocked_wis = match wis.write() {
+        Ok(unlocked_wis) => unlocked_wis,
+        Err(error) => {
+            return Ok(HttpResponse::from_error(error::ErrorInternalServerError(
+                format!("Failed to get lock on cache: {}", error),
+            )))
+        }
+    };
+    *unlocked_wis = new_wis;
+    Ok(HttpResponse::Ok().into())
+}
+
 async fn info(
     wis: web::Data<RwLock<wisard::dict_wisard::Wisard<u8>>>,
 ) -> Result<HttpResponse, Error> {
@@ -80,6 +143,7 @@ async fn info(
         hashtables: hashtables,
         addresses: addresses,
         bleach: bleach,
+        seed: unlocked_wis.get_seed(),
     }))
 }
 
@@ -108,20 +172,21 @@ async fn train(
         }
     };
 
-    match unlocked_wis.train(v, label) {
+    match unlocked_wis.train(&v, label) {
         Ok(_) => return Ok(HttpResponse::Ok().into()),
-        Err(error) => {
-            return Ok(HttpResponse::from_error(error::ErrorInternalServerError(
-                format!("Wisard internal error: {}", error),
-            )))
-        }
+        Err(error) => return Ok(wisard_error(error)),
     }
 }
 
+// ?k=3 adds the 3 best candidates to the response
 async fn classify(
     wis: web::Data<RwLock<wisard::dict_wisard::Wisard<u8>>>,
+    web::Query(query): web::Query<ClassifyQuery>,
     mut payload: web::Payload,
 ) -> Result<HttpResponse, Error> {
+    if query.k == Some(0) {
+        return Err(error::ErrorBadRequest("k must be at least 1"));
+    }
     let mut v = Vec::new();
     while let Some(chunk) = payload.next().await {
         let data = chunk?;
@@ -141,20 +206,68 @@ async fn classify(
         }
     };
 
-    match unlocked_wis.classify(v) {
-        Ok(label) => return Ok(HttpResponse::Ok().json(ClassifyResponse { label: label })),
+    let prediction = match unlocked_wis.classify_with_scores(&v) {
+        Ok(prediction) => prediction,
+        Err(error) => return Ok(wisard_error(error)),
+    };
+    // what classify_top_k returns, without classifying the sample again
+    let candidates = query
+        .k
+        .map(|k| prediction.scores.iter().take(k).cloned().collect());
+    match prediction.outcome {
+        Outcome::Label(label) => Ok(HttpResponse::Ok().json(ClassifyResponse {
+            label: Some(label),
+            rejected: false,
+            candidates,
+        })),
+        // the reject rule keeps the guess from the client, not the candidates
+        Outcome::Rejected => Ok(HttpResponse::Ok().json(ClassifyResponse {
+            label: None,
+            rejected: true,
+            candidates,
+        })),
+        // the tie breaker is Ambiguous, the client gets the tied labels
+        Outcome::Ambiguous(labels) => {
+            let error = WisardError::WisardAmbiguous(labels.len());
+            Ok(HttpResponse::Conflict().json(AmbiguousResponse {
+                code: error.code().to_string(),
+                message: error.to_string(),
+                labels,
+            }))
+        }
+    }
+}
+
+// sets the fixed bleach and whether it's raised on ties, without erasing
+async fn bleach(
+    wis: web::Data<RwLock<wisard::dict_wisard::Wisard<u8>>>,
+    web::Query(query): web::Query<BleachQuery>,
+) -> Result<HttpResponse, Error> {
+    let mut unlocked_wis = match wis.write() {
+        Ok(unlocked_wis) => unlocked_wis,
         Err(error) => {
             return Ok(HttpResponse::from_error(error::ErrorInternalServerError(
-                format!("Wisard internal error: {}", error),
+                format!("Failed to get lock on cache: {}", error),
             )))
         }
+    };
+    if let Some(bleach) = query.bleach {
+        unlocked_wis.set_bleach(bleach);
+    }
+    match query.dynamic {
+        Some(true) => unlocked_wis.set_bleach_policy(BleachPolicy::Dynamic),
+        Some(false) => unlocked_wis.set_bleach_policy(BleachPolicy::Fixed),
+        None => (),
     }
+    Ok(HttpResponse::Ok().into())
 }
 
-async fn save(
+// picks the fixed bleach that does best on a validation set
+async fn tune(
     wis: web::Data<RwLock<wisard::dict_wisard::Wisard<u8>>>,
+    validation: web::Json<TuneRequest>,
 ) -> Result<HttpResponse, Error> {
-    let unlocked_wis = match wis.read() {
+    let mut unlocked_wis = match wis.write() {
         Ok(unlocked_wis) => unlocked_wis,
         Err(error) => {
             return Ok(HttpResponse::from_error(error::ErrorInternalServerError(
@@ -162,23 +275,78 @@ async fn save(
             )))
         }
     };
-    let encoded = match unlocked_wis.save() {
-        Ok(e) => e,
+    match unlocked_wis.tune_bleach(&validation.samples, &validation.labels) {
+        Ok(bleach) => Ok(HttpResponse::Ok().json(TuneResponse { bleach })),
+        Err(error) => Ok(wisard_error(error)),
+    }
+}
+
+// replaces the class weights with a JSON object of label to weight, without
+// erasing the model
+async fn weights(
+    wis: web::Data<RwLock<wisard::dict_wisard::Wisard<u8>>>,
+    class_weights: web::Json<HashMap<String, f64>>,
+) -> Result<HttpResponse, Error> {
+    let mut unlocked_wis = match wis.write() {
+        Ok(unlocked_wis) => unlocked_wis,
         Err(error) => {
             return Ok(HttpResponse::from_error(error::ErrorInternalServerError(
-                format!("Wisard found an error while saving: {}", error),
+                format!("Failed to get lock on cache: {}", error),
             )))
         }
     };
+    match unlocked_wis.set_class_weights(class_weights.into_inner()) {
+        Ok(()) => Ok(HttpResponse::Ok().into()),
+        Err(error) => Ok(wisard_error(error)),
+    }
+}
+
+// the first export format named by a header such as Accept, None means bincode
+fn negotiate(req: &HttpRequest, name: header::HeaderName) -> Option<ExportFormat> {
+    req.headers()
+        .get(name)?
+        .to_str()
+        .ok()?
+        .split(',')
+        .find_map(ExportFormat::from_content_type)
+}
+
+async fn save(
+    req: HttpRequest,
+    wis: web::Data<RwLock<wisard::dict_wisard::Wisard<u8>>>,
+) -> Result<HttpResponse, Error> {
+    let unlocked_wis = match wis.read() {
+        Ok(unlocked_wis) => unlocked_wis,
+        Err(error) => {
+            return Ok(HttpResponse::from_error(error::ErrorInternalServerError(
+                format!("Failed to get lock on cache: {}", error),
+            )))
+        }
+    };
+    let format = negotiate(&req, header::ACCEPT);
+    let encoded = match &format {
+        Some(format) => unlocked_wis.export(format),
+        None => unlocked_wis.save(),
+    };
+    let encoded = match encoded {
+        Ok(e) => e,
+        Err(error) => return Ok(wisard_error(error)),
+    };
+    let content_type = match &format {
+        Some(format) => format.content_type(),
+        None => "application/octet-stream",
+    };
 
     Ok(HttpResponse::Ok()
         .encoding(ContentEncoding::Gzip)
+        .content_type(content_type)
         .body(encoded))
 }
 
 const WEIGHT_MAX_SIZE: usize = 500_000_000; // 500MB limit
 
 async fn load(
+    req: HttpRequest,
     wis: web::Data<RwLock<wisard::dict_wisard::Wisard<u8>>>,
     mut payload: web::Payload,
 ) -> Result<HttpResponse, Error> {
@@ -202,13 +370,17 @@ async fn load(
         }
     };
 
-    match unlocked_wis.load(&v) {
+    let limits = LoadLimits {
+        max_bytes: WEIGHT_MAX_SIZE as u64,
+        ..LoadLimits::default()
+    };
+    let loaded = match negotiate(&req, header::CONTENT_TYPE) {
+        Some(format) => unlocked_wis.import_bounded(&v, &format, &limits),
+        None => unlocked_wis.load_bounded(&v, &limits),
+    };
+    match loaded {
         Ok(_) => return Ok(HttpResponse::Ok().into()),
-        Err(error) => {
-            return Ok(HttpResponse::from_error(error::ErrorInternalServerError(
-                format!("Wisard internal error: {}", error),
-            )))
-        }
+        Err(error) => return Ok(wisard_error(error)),
     }
 }
 
@@ -233,9 +405,74 @@ struct ModelInfo {
     hashtables: u16,
     addresses: u16,
     bleach: u16,
+    seed: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct BleachQuery {
+    bleach: Option<u16>,
+    dynamic: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct TuneRequest {
+    samples: Vec<Vec<u8>>,
+    labels: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct TuneResponse {
+    bleach: u16,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct ErrorResponse {
+    code: String,
+    message: String,
+}
+
+// the status of a wisard error follows its code, the body carries both
+fn wisard_error(error: WisardError) -> HttpResponse {
+    let status = match error.code() {
+        "input_too_short" | "length_mismatch" | "incomparable" | "incompatible"
+        | "invalid_config" => StatusCode::BAD_REQUEST,
+        "unknown_label" | "not_trained" => StatusCode::NOT_FOUND,
+        "empty_model" | "ambiguous" => StatusCode::CONFLICT,
+        "limit_exceeded" => StatusCode::PAYLOAD_TOO_LARGE,
+        "malformed"
+        | "invalid_model"
+        | "unknown_format"
+        | "unsupported_version"
+        | "type_mismatch"
+        | "checksum_mismatch"
+        | "unsupported"
+        | "rejected" => StatusCode::UNPROCESSABLE_ENTITY,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    HttpResponse::build(status).json(ErrorResponse {
+        code: error.code().to_string(),
+        message: error.to_string(),
+    })
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 struct ClassifyResponse {
-    label: String,
+    // None when the sample is rejected
+    label: Option<String>,
+    rejected: bool,
+    // the k best classes, only when k is asked for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    candidates: Option<Vec<Score>>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct ClassifyQuery {
+    k: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct AmbiguousResponse {
+    code: String,
+    message: String,
+    labels: Vec<String>,
 }
@@ -0,0 +1,90 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use dataloaders::mnist;
+use std::fs::File;
+use wisard::dict_wisard::{Scratch, Wisard};
+
+// the first `limit` images of an MNIST split, with their labels
+fn load(labels: &str, images: &str, limit: usize) -> (Vec<Vec<u8>>, Vec<u8>) {
+    let label_data = mnist::MnistData::new(&File::open(labels).expect("MNIST labels not found"))
+        .expect("unable to parse MNIST labels");
+    let images_data = mnist::MnistData::new(&File::open(images).expect("MNIST images not found"))
+        .expect("unable to parse MNIST images");
+    let image_shape = (images_data.sizes[1] * images_data.sizes[2]) as usize;
+    let count = limit.min(images_data.sizes[0] as usize);
+
+    let images = (0..count)
+        .map(|i| images_data.data[i * image_shape..(i + 1) * image_shape].to_vec())
+        .collect();
+    let labels = label_data.data[..count].to_vec();
+    (images, labels)
+}
+
+fn mnist_benchmark(c: &mut Criterion) {
+    let (train_images, train_labels) = load(
+        "data/mnist/train-labels-idx1-ubyte.gz",
+        "data/mnist/train-images-idx3-ubyte.gz",
+        10_000,
+    );
+    let (test_images, _) = load(
+        "data/mnist/t10k-labels-idx1-ubyte.gz",
+        "data/mnist/t10k-images-idx3-ubyte.gz",
+        10_000,
+    );
+
+    let mut group = c.benchmark_group("mnist_train");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(train_images.len() as u64));
+    group.bench_function("train", |b| {
+        b.iter(|| {
+            let mut wis = Wisard::<u8, u8>::with_seed(28, 28, 0, 0);
+            for (image, label) in train_images.iter().zip(train_labels.iter()) {
+                wis.train(image, *label).unwrap();
+            }
+        })
+    });
+    group.bench_function("train_with_scratch", |b| {
+        b.iter(|| {
+            let mut wis = Wisard::<u8, u8>::with_seed(28, 28, 0, 0);
+            let mut scratch = Scratch::new();
+            for (image, label) in train_images.iter().zip(train_labels.iter()) {
+                wis.train_with_scratch(image, *label, &mut scratch).unwrap();
+            }
+        })
+    });
+    group.bench_function("train_batch", |b| {
+        b.iter(|| {
+            let mut wis = Wisard::<u8, u8>::with_seed(28, 28, 0, 0);
+            wis.train_batch(&train_images, &train_labels).unwrap();
+        })
+    });
+    group.finish();
+
+    let mut wis = Wisard::<u8, u8>::with_seed(28, 28, 0, 0);
+    wis.train_batch(&train_images, &train_labels).unwrap();
+
+    let mut group = c.benchmark_group("mnist_classify");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(test_images.len() as u64));
+    group.bench_function("classify", |b| {
+        b.iter(|| {
+            for image in test_images.iter() {
+                wis.classify(image).unwrap();
+            }
+        })
+    });
+    group.bench_function("classify_with_scratch", |b| {
+        b.iter(|| {
+            let mut scratch = Scratch::new();
+            for image in test_images.iter() {
+                wis.classify_with_scratch(image, &mut scratch).unwrap();
+            }
+        })
+    });
+    group.bench_function("classify_batch", |b| {
+        b.iter(|| wis.classify_batch(&test_images).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, mnist_benchmark);
+criterion_main!(benches);
@@ -3,66 +3,235 @@
 extern crate rocket;
 extern crate wisard;
 
+use rocket::http::Status;
+use rocket::response::status::Custom;
 use rocket::response::Stream;
 use rocket::State;
+use serde::Deserialize;
+use std::collections::HashMap;
 use std::io::Cursor;
 use std::sync::{Arc, Mutex};
+use wisard::config::WisardConfig;
+use wisard::dict_wisard::{BleachPolicy, LoadLimits};
+use wisard::errors::WisardError;
+
+const WEIGHT_MAX_SIZE: u64 = 500_000_000; // 500MB limit
+
+// the status of a wisard error follows its code, the body carries both
+fn wisard_error(error: WisardError) -> Custom<String> {
+    let status = match error.code() {
+        "input_too_short" | "length_mismatch" | "incomparable" | "incompatible"
+        | "invalid_config" => Status::BadRequest,
+        "unknown_label" | "not_trained" => Status::NotFound,
+        "empty_model" | "ambiguous" => Status::Conflict,
+        "limit_exceeded" => Status::PayloadTooLarge,
+        "malformed"
+        | "invalid_model"
+        | "unknown_format"
+        | "unsupported_version"
+        | "type_mismatch"
+        | "checksum_mismatch"
+        | "unsupported"
+        | "rejected" => Status::UnprocessableEntity,
+        _ => Status::InternalServerError,
+    };
+    Custom(status, format!("{}: {}", error.code(), error))
+}
+
+// models come from clients, so they're loaded within limits
+fn load_limits() -> LoadLimits {
+    LoadLimits {
+        max_bytes: WEIGHT_MAX_SIZE,
+        ..LoadLimits::default()
+    }
+}
 
 pub fn ignite() {
     let wis = Arc::new(Mutex::new(wisard::dict_wisard::Wisard::<u8>::new()));
     rocket::ignite()
         .mount(
             "/",
-            routes![new, with_model, train, classify, save, load, erase],
+            routes![
+                new,
+                config_toml,
+                config_json,
+                bleach,
+                tune,
+                weights,
+                with_model,
+                train,
+                classify,
+                save,
+                load,
+                erase
+            ],
         )
         .manage(wis)
         .launch();
 }
 
-#[post("/new?<hashtables>&<addresses>&<bleach>")]
+#[post("/new?<hashtables>&<addresses>&<bleach>&<seed>")]
 pub fn new(
     wis: State<Arc<Mutex<wisard::dict_wisard::Wisard<u8>>>>,
     hashtables: u16,
     addresses: u16,
     bleach: u16,
+    seed: Option<u64>,
+) -> Result<(), Custom<String>> {
+    let mut unlocked_wis = wis.lock().unwrap();
+    // checked like /config, the model keeps its RAM kind
+    let mut config = WisardConfig::new()
+        .without_input_length()
+        .number_of_hashtables(hashtables)
+        .addr_length(addresses)
+        .bleach(bleach)
+        .ram(unlocked_wis.get_ram_kind().clone());
+    if let Some(seed) = seed {
+        config = config.seed(seed);
+    }
+    config.validate().map_err(wisard_error)?;
+    unlocked_wis.erase_and_change_hyperparameters(hashtables, addresses, bleach, seed);
+    Ok(())
+}
+
+// replaces the model with the one a WisardConfig describes
+fn replace_with_config(
+    wis: State<Arc<Mutex<wisard::dict_wisard::Wisard<u8>>>>,
+    config: Result<WisardConfig, WisardError>,
+) -> Result<(), Custom<String>> {
+    let new_wis = config
+        .and_then(|config| wisard::dict_wisard::Wisard::with_config(&config))
+        .map_err(wisard_error)?;
+    *wis.lock().unwrap() = new_wis;
+    Ok(())
+}
+
+#[post("/config", format = "application/toml", data = "<config>")]
+pub fn config_toml(
+    wis: State<Arc<Mutex<wisard::dict_wisard::Wisard<u8>>>>,
+    config: String,
+) -> Result<(), Custom<String>> {
+    replace_with_config(wis, WisardConfig::from_toml(&config))
+}
+
+#[post("/config", format = "json", data = "<config>", rank = 2)]
+pub fn config_json(
+    wis: State<Arc<Mutex<wisard::dict_wisard::Wisard<u8>>>>,
+    config: String,
+) -> Result<(), Custom<String>> {
+    replace_with_config(wis, WisardConfig::from_json(&config))
+}
+
+// sets the fixed bleach and whether it's raised on ties, without erasing
+#[post("/bleach?<bleach>&<dynamic>")]
+pub fn bleach(
+    wis: State<Arc<Mutex<wisard::dict_wisard::Wisard<u8>>>>,
+    bleach: Option<u16>,
+    dynamic: Option<bool>,
 ) {
+    let mut unlocked_wis = wis.lock().unwrap();
+    if let Some(bleach) = bleach {
+        unlocked_wis.set_bleach(bleach);
+    }
+    match dynamic {
+        Some(true) => unlocked_wis.set_bleach_policy(BleachPolicy::Dynamic),
+        Some(false) => unlocked_wis.set_bleach_policy(BleachPolicy::Fixed),
+        None => (),
+    }
+}
+
+#[derive(Deserialize)]
+struct TuneRequest {
+    samples: Vec<Vec<u8>>,
+    labels: Vec<String>,
+}
+
+// picks the fixed bleach that does best on a validation set, given as JSON
+// {"samples": [[...]], "labels": [...]}, and answers with it
+#[post("/tune", format = "json", data = "<validation>")]
+pub fn tune(
+    wis: State<Arc<Mutex<wisard::dict_wisard::Wisard<u8>>>>,
+    validation: String,
+) -> Result<String, Custom<String>> {
+    let validation: TuneRequest = serde_json::from_str(&validation)
+        .map_err(|error| wisard_error(WisardError::WisardMalformed(Box::new(error))))?;
+    wis.lock()
+        .unwrap()
+        .tune_bleach(&validation.samples, &validation.labels)
+        .map(|bleach| bleach.to_string())
+        .map_err(wisard_error)
+}
+
+// replaces the class weights with a JSON object of label to weight, without
+// erasing the model
+#[post("/weights", format = "json", data = "<class_weights>")]
+pub fn weights(
+    wis: State<Arc<Mutex<wisard::dict_wisard::Wisard<u8>>>>,
+    class_weights: String,
+) -> Result<(), Custom<String>> {
+    let class_weights: HashMap<String, f64> = serde_json::from_str(&class_weights)
+        .map_err(|error| wisard_error(WisardError::WisardMalformed(Box::new(error))))?;
     wis.lock()
         .unwrap()
-        .erase_and_change_hyperparameters(hashtables, addresses, bleach);
+        .set_class_weights(class_weights)
+        .map_err(wisard_error)
 }
 
 #[post("/with_model", format = "multipart", data = "<model>")]
-pub fn with_model(wis: State<Arc<Mutex<wisard::dict_wisard::Wisard<u8>>>>, model: ModelMultipart) {
-    let mut unlocked_wis = wis.lock().unwrap();
-    unlocked_wis.erase_and_change_hyperparameters(
+pub fn with_model(
+    wis: State<Arc<Mutex<wisard::dict_wisard::Wisard<u8>>>>,
+    model: ModelMultipart,
+) -> Result<(), Custom<String>> {
+    // the served model stays as it was if the upload doesn't load
+    let mut loaded = wisard::dict_wisard::Wisard::<u8>::with_params(
         model.number_of_hashtables,
         model.addr_length,
         model.bleach,
     );
-    unlocked_wis.load(&model.weights).unwrap();
+    loaded
+        .load_bounded(&model.weights, &load_limits())
+        .map_err(wisard_error)?;
+    *wis.lock().unwrap() = loaded;
+    Ok(())
 }
 
 #[post("/train", format = "multipart", data = "<image>")]
-pub fn train(wis: State<Arc<Mutex<wisard::dict_wisard::Wisard<u8>>>>, image: TrainImageMultipart) {
+pub fn train(
+    wis: State<Arc<Mutex<wisard::dict_wisard::Wisard<u8>>>>,
+    image: TrainImageMultipart,
+) -> Result<(), Custom<String>> {
     let mut unlocked_wis = wis.lock().unwrap();
-    unlocked_wis.train(image.image, image.label).unwrap();
+    unlocked_wis
+        .train(&image.image, image.label)
+        .map_err(wisard_error)
 }
 
 #[post("/classify", format = "multipart", data = "<image>")]
 pub fn classify(
     wis: State<Arc<Mutex<wisard::dict_wisard::Wisard<u8>>>>,
     image: ClassifyImageMultipart,
-) -> String {
-    return wis.lock().unwrap().classify(image.image).unwrap();
+) -> Result<String, Custom<String>> {
+    wis.lock()
+        .unwrap()
+        .classify(&image.image)
+        .map_err(wisard_error)
 }
 #[get("/model")]
-pub fn save(wis: State<Arc<Mutex<wisard::dict_wisard::Wisard<u8>>>>) -> Stream<Cursor<Vec<u8>>> {
-    let encoded: Vec<u8> = wis.lock().unwrap().save().unwrap();
-    Stream::from(Cursor::new(encoded))
+pub fn save(
+    wis: State<Arc<Mutex<wisard::dict_wisard::Wisard<u8>>>>,
+) -> Result<Stream<Cursor<Vec<u8>>>, Custom<String>> {
+    let encoded: Vec<u8> = wis.lock().unwrap().save().map_err(wisard_error)?;
+    Ok(Stream::from(Cursor::new(encoded)))
 }
 #[post("/model", format = "multipart", data = "<weights>")]
-pub fn load(wis: State<Arc<Mutex<wisard::dict_wisard::Wisard<u8>>>>, weights: ModelMultipart) {
-    wis.lock().unwrap().load(&weights.weights).unwrap();
+pub fn load(
+    wis: State<Arc<Mutex<wisard::dict_wisard::Wisard<u8>>>>,
+    weights: ModelMultipart,
+) -> Result<(), Custom<String>> {
+    wis.lock()
+        .unwrap()
+        .load_bounded(&weights.weights, &load_limits())
+        .map_err(wisard_error)
 }
 #[delete("/model")]
 pub fn erase(wis: State<Arc<Mutex<wisard::dict_wisard::Wisard<u8>>>>) {
@@ -89,8 +258,12 @@ impl<'a> FromData<'a> for ModelMultipart {
     type Error = ();
 
     fn transform(_request: &Request, data: Data) -> Transform<Outcome<Self::Owned, Self::Error>> {
+        // limit max size of in-memory payload, load_bounded rejects what's cut
         let mut d = Vec::new();
-        data.stream_to(&mut d).expect("Unable to read");
+        data.open()
+            .take(WEIGHT_MAX_SIZE)
+            .read_to_end(&mut d)
+            .expect("Unable to read");
 
         Transform::Owned(Outcome::Success(d))
     }
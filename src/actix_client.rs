@@ -34,12 +34,13 @@ async fn main() -> surf::Result<()> {
 
     for (image, classification) in images.into_iter().zip(classifications.into_iter()) {
         // let tuple: (String, f64, f64);
-        let ClassifyResponse { label } = surf::post("http://localhost:8080/classify")
+        let ClassifyResponse { label, .. } = surf::post("http://localhost:8080/classify")
             .body(Body::from_bytes(image))
             .recv_json()
             .await?;
         // tuple = wis.classify(image);
-        if label == classification.to_string() {
+        // a rejected image counts as a miss
+        if label == Some(classification.to_string()) {
             hit += 1;
         }
         count += 1;
@@ -53,5 +54,6 @@ async fn main() -> surf::Result<()> {
 
 #[derive(Debug, Deserialize, Serialize)]
 struct ClassifyResponse {
-    label: String,
+    label: Option<String>,
+    rejected: bool,
 }
@@ -1,10 +1,11 @@
 use dataloaders::mnist;
 use std::env;
 use std::error::Error;
-use std::fs::File;
+use std::fs::{self, File};
 use std::process;
 use std::time::Instant;
-use wisard::dict_wisard;
+use wisard::config::WisardConfig;
+use wisard::dict_wisard::{self, Outcome};
 
 fn main() {
     let config = Config::new(env::args()).unwrap_or_else(|err| {
@@ -21,15 +22,14 @@ fn main() {
 
 pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
     println!("Rusty WiSARD - MNIST 🦀🦀🦀");
-    println!("Number of hashtables: {}", config.number_of_hashtables);
-    println!("Address size: {}", config.address_size);
-    println!("Bleaching: {}", config.bleach);
-
-    let mut wis = dict_wisard::Wisard::with_params(
-        config.number_of_hashtables.parse::<u16>()?,
-        config.address_size.parse::<u16>()?,
-        config.bleach.parse::<u16>()?,
-    );
+    let mut wis: dict_wisard::Wisard<u8, u8> = dict_wisard::Wisard::with_config(&config.model)?;
+    let (number_of_hashtables, address_size, bleach) = wis.get_info();
+    println!("Number of hashtables: {}", number_of_hashtables);
+    println!("Address size: {}", address_size);
+    println!("Bleaching: {}", bleach);
+    if let Some(seed) = wis.get_seed() {
+        println!("Seed: {}", seed);
+    }
     println!("\n-----------------\nTraining\n-----------------");
     let now = Instant::now();
 
@@ -55,9 +55,7 @@ pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
     );
 
     let now = Instant::now();
-    for (image, classification) in images.into_iter().zip(classifications.into_iter()) {
-        wis.train(image, classification.to_string())?;
-    }
+    wis.train_batch(&images, &classifications)?;
     println!("Training took: {} milliseconds", now.elapsed().as_millis());
 
     println!("\n-----------------\nTesting\n-----------------");
@@ -82,54 +80,95 @@ pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
         now.elapsed().as_millis()
     );
     let mut hit: u64 = 0;
+    let mut ambiguous: u64 = 0;
+    let mut rejected: u64 = 0;
     let mut count: u64 = 0;
 
     let now = Instant::now();
 
-    for (image, classification) in images.into_iter().zip(classifications.into_iter()) {
-        let label: String = wis.classify(image)?;
-        if label == classification.to_string() {
-            hit += 1;
+    let predictions = wis.classify_batch(&images)?;
+    for (prediction, classification) in predictions.into_iter().zip(classifications) {
+        match prediction.outcome {
+            Outcome::Label(label) if label == classification => hit += 1,
+            // counted apart, so ties and rejections don't read as mistakes
+            Outcome::Ambiguous(_) => ambiguous += 1,
+            Outcome::Rejected => rejected += 1,
+            _ => (),
         }
         count += 1;
     }
     println!("Testing took: {} milliseconds", now.elapsed().as_millis());
 
+    let accepted = count - rejected;
     println!("Accuracy: {}", hit as f64 / count as f64);
+    println!("Ambiguous: {} of {}", ambiguous, count);
+    println!("Reject rate: {}", rejected as f64 / count as f64);
+    if accepted > 0 {
+        println!("Accuracy on accepted: {}", hit as f64 / accepted as f64);
+    }
 
     Ok(())
 }
 
+const MNIST_PIXELS: usize = 784;
+
+// either the path of a .json or .toml WisardConfig, or the number of
+// hashtables, address size, bleach and seed as positional arguments. The
+// hashtables times the address size must be the 784 pixels of an image
 pub struct Config {
-    // pub filename: String,
-    pub number_of_hashtables: String,
-    pub address_size: String,
-    pub bleach: String,
+    pub model: WisardConfig,
 }
 
 impl Config {
-    pub fn new(mut args: env::Args) -> Result<Config, &'static str> {
+    pub fn new(mut args: env::Args) -> Result<Config, Box<dyn Error>> {
         args.next();
 
-        let number_of_hashtables = match args.next() {
+        let first = match args.next() {
             Some(arg) => arg,
-            None => return Err("Didn't get number of hashtables size"),
+            None => return Err("Didn't get a config file or number of hashtables".into()),
         };
 
+        if first.ends_with(".json") {
+            let model = WisardConfig::from_json(&fs::read_to_string(&first)?)?;
+            return Ok(Config { model });
+        }
+        if first.ends_with(".toml") {
+            let model = WisardConfig::from_toml(&fs::read_to_string(&first)?)?;
+            return Ok(Config { model });
+        }
+
+        let number_of_hashtables = first.parse::<u16>()?;
+
         let address_size = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get address size"),
+            Some(arg) => arg.parse::<u16>()?,
+            None => return Err("Didn't get address size".into()),
         };
 
         let bleach = match args.next() {
-            Some(arg) => arg,
-            None => String::from("0"),
+            Some(arg) => arg.parse::<u16>()?,
+            None => 0,
         };
 
-        Ok(Config {
-            number_of_hashtables,
-            address_size,
-            bleach,
-        })
+        // the images are read whole, a config file can set a remainder policy
+        // for tuples that don't tile them
+        let pixels = number_of_hashtables as usize * address_size as usize;
+        if pixels != MNIST_PIXELS {
+            return Err(format!(
+                "{} hashtables with an address size of {} read {} pixels, an MNIST image has {}; other sizes need a config file with a remainder policy",
+                number_of_hashtables, address_size, pixels, MNIST_PIXELS
+            )
+            .into());
+        }
+
+        let mut model = WisardConfig::new()
+            .input_length(MNIST_PIXELS)
+            .number_of_hashtables(number_of_hashtables)
+            .addr_length(address_size)
+            .bleach(bleach);
+        if let Some(seed) = args.next() {
+            model = model.seed(seed.parse::<u64>()?);
+        }
+
+        Ok(Config { model })
     }
 }
@@ -0,0 +1,574 @@
+use crate::dict_wisard::{
+    elect, BleachPolicy, Discriminator, Election, Normalization, Prediction, RamKind, RejectRule,
+    TieBreaker,
+};
+use crate::errors::WisardError;
+use crate::format::{self, Header, TypeTag};
+use crate::mapping;
+use rand::seq::SliceRandom;
+use rand::thread_rng;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::Hash;
+use std::io::Write;
+use std::marker::PhantomData;
+use std::path::Path;
+
+// turns every input value into one or more bits
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub enum Binarizer {
+    // a single bit, set when the value is above the threshold
+    Threshold(f64),
+    // `bits` bits, the number of bits set grows linearly from min to max
+    Thermometer { min: f64, max: f64, bits: u16 },
+    // `bits` bits with a block of bits / 2 ones that rotates from min to max,
+    // for cyclic values such as angles or hours
+    CircularThermometer { min: f64, max: f64, bits: u16 },
+}
+
+impl Binarizer {
+    pub fn bits_per_value(&self) -> u16 {
+        match self {
+            Binarizer::Threshold(_) => 1,
+            Binarizer::Thermometer { bits, .. } => *bits,
+            Binarizer::CircularThermometer { bits, .. } => *bits,
+        }
+    }
+
+    // thermometers need at least one bit and a range to spread them over
+    pub fn check(&self) -> Result<(), WisardError> {
+        match *self {
+            Binarizer::Threshold(_) => Ok(()),
+            Binarizer::Thermometer { min, max, bits }
+            | Binarizer::CircularThermometer { min, max, bits } => {
+                if bits == 0 {
+                    return Err(WisardError::WisardInvalidConfig(
+                        "a thermometer needs at least one bit".to_string(),
+                    ));
+                }
+                if max.partial_cmp(&min) != Some(Ordering::Greater) {
+                    return Err(WisardError::WisardInvalidConfig(format!(
+                        "a thermometer needs max above min, got min {} and max {}",
+                        min, max
+                    )));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    pub fn encode(&self, value: f64, out: &mut Vec<bool>) {
+        match *self {
+            Binarizer::Threshold(threshold) => out.push(value > threshold),
+            Binarizer::Thermometer { min, max, bits } => {
+                let step = (max - min) / (bits as f64 + 1.0);
+                for i in 0..bits {
+                    out.push(value > min + step * (i as f64 + 1.0));
+                }
+            }
+            Binarizer::CircularThermometer { min, max, bits } => {
+                let bits = bits as i64;
+                let width = (bits / 2).max(1);
+                let position = if max > min {
+                    ((value - min) / (max - min) * bits as f64).floor() as i64
+                } else {
+                    0
+                };
+                let position = position.clamp(0, (bits - 1).max(0));
+                for i in 0..bits {
+                    out.push((i - position).rem_euclid(bits) < width);
+                }
+            }
+        }
+    }
+}
+
+const MODEL_NAME: &str = "binary_wisard";
+// the bits of a tuple are packed into a single u64 address, and the address
+// with all 64 bits set is the one that never votes
+const MAX_ADDR_LENGTH: u16 = 63;
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(bound(
+    serialize = "L: Serialize + Eq + Hash",
+    deserialize = "L: Deserialize<'de> + Eq + Hash"
+))]
+pub struct Wisard<T, L = String> {
+    discs: HashMap<L, Discriminator>,
+    binarizer: Binarizer,
+    addr_length: u16,
+    number_of_hashtables: u16,
+    mapping: Vec<u64>,
+    bleach: u16,
+    phantom: PhantomData<T>,
+}
+
+impl<T, L> Wisard<T, L> {
+    pub fn get_info(&self) -> (u16, u16, u16) {
+        (self.number_of_hashtables, self.addr_length, self.bleach)
+    }
+
+    pub fn get_binarizer(&self) -> &Binarizer {
+        &self.binarizer
+    }
+
+    pub fn new() -> Self {
+        // MNIST: 784 pixels, one bit each
+        Wisard::build(28, 28, 0, Binarizer::Threshold(127.0))
+    }
+
+    pub fn with_params(
+        number_of_hashtables: u16,
+        addr_length: u16,
+        bleach: u16,
+        binarizer: Binarizer,
+    ) -> Result<Self, WisardError> {
+        if addr_length == 0 || addr_length > MAX_ADDR_LENGTH {
+            return Err(WisardError::WisardInvalidConfig(format!(
+                "addr_length is {}, it must be between 1 and {} to fit in a u64 address",
+                addr_length, MAX_ADDR_LENGTH
+            )));
+        }
+        binarizer.check()?;
+        Ok(Wisard::build(
+            number_of_hashtables,
+            addr_length,
+            bleach,
+            binarizer,
+        ))
+    }
+
+    fn build(
+        number_of_hashtables: u16,
+        addr_length: u16,
+        bleach: u16,
+        binarizer: Binarizer,
+    ) -> Self {
+        // randomizes the mapping of bits to hashtables
+        let mut rng_mapping =
+            (0..addr_length as u64 * number_of_hashtables as u64).collect::<Vec<u64>>();
+        rng_mapping.shuffle(&mut thread_rng());
+
+        Wisard::<T, L> {
+            discs: HashMap::new(),
+            binarizer,
+            addr_length,
+            number_of_hashtables,
+            mapping: rng_mapping,
+            bleach,
+            phantom: PhantomData,
+        }
+    }
+
+    fn addresses(&self, data: &[T]) -> Result<Vec<u64>, WisardError>
+    where
+        T: Into<f64> + Copy,
+    {
+        let bits_per_value = self.binarizer.bits_per_value().max(1) as usize;
+        // enough values to cover the highest bit the mapping reads
+        let expected = self
+            .mapping
+            .iter()
+            .max()
+            .map_or(0, |&bit| bit as usize / bits_per_value + 1);
+        if data.len() < expected {
+            return Err(WisardError::WisardInputTooShort {
+                expected,
+                actual: data.len(),
+            });
+        }
+        let mut bits = Vec::with_capacity(data.len() * bits_per_value);
+        for value in data {
+            self.binarizer.encode((*value).into(), &mut bits);
+        }
+
+        let mut addresses = Vec::with_capacity(self.number_of_hashtables as usize);
+        for tuple in self.mapping.chunks(self.addr_length as usize) {
+            let mut address: u64 = 0;
+            for (j, &i) in tuple.iter().enumerate() {
+                let bit = bits.get(i as usize).ok_or(WisardError::WisardInvalidModel(
+                    "the mapping reads past the input",
+                ))?;
+                if *bit {
+                    address |= 1 << j;
+                }
+            }
+            addresses.push(address);
+        }
+        Ok(addresses)
+    }
+
+    pub fn train(&mut self, data: &[T], label: L) -> Result<(), WisardError>
+    where
+        T: Into<f64> + Copy,
+        L: Eq + Hash,
+    {
+        let addresses = self.addresses(data)?;
+        let number_of_hashtables = self.number_of_hashtables;
+        self.discs
+            .entry(label)
+            .or_insert_with(|| Discriminator::new(number_of_hashtables))
+            .train(&addresses)
+    }
+
+    // forgets a sample trained with label, a discriminator that ends up with
+    // no training at all is dropped
+    pub fn untrain(&mut self, data: &[T], label: &L) -> Result<(), WisardError>
+    where
+        T: Into<f64> + Copy,
+        L: Eq + Hash,
+    {
+        let addresses = self.addresses(data)?;
+        let disc = self
+            .discs
+            .get_mut(label)
+            .ok_or(WisardError::WisardUnknownLabel)?;
+        disc.untrain(&addresses)?;
+        if disc.get_times_trained() == 0 {
+            self.discs.remove(label);
+        }
+        Ok(())
+    }
+
+    pub fn remove_class(&mut self, label: &L) -> Result<(), WisardError>
+    where
+        L: Eq + Hash,
+    {
+        self.discs
+            .remove(label)
+            .map(|_| ())
+            .ok_or(WisardError::WisardUnknownLabel)
+    }
+
+    // adds the training of other, which must share every hyperparameter and
+    // the mapping
+    pub fn merge(&mut self, other: &Wisard<T, L>) -> Result<(), WisardError>
+    where
+        L: Eq + Hash + Clone,
+    {
+        if self.get_info() != other.get_info()
+            || self.binarizer != other.binarizer
+            || self.mapping != other.mapping
+        {
+            return Err(WisardError::WisardIncompatible);
+        }
+        for (label, other_disc) in &other.discs {
+            let number_of_hashtables = self.number_of_hashtables;
+            self.discs
+                .entry(label.clone())
+                .or_insert_with(|| Discriminator::new(number_of_hashtables))
+                .merge(other_disc, None)?;
+        }
+        Ok(())
+    }
+
+    pub fn classify(&self, data: &[T]) -> Result<L, WisardError>
+    where
+        T: Into<f64> + Copy,
+        L: Ord + Hash + Clone,
+    {
+        self.classify_with_scores(data)?.into_label()
+    }
+
+    pub fn classify_with_scores(&self, data: &[T]) -> Result<Prediction<L>, WisardError>
+    where
+        T: Into<f64> + Copy,
+        L: Ord + Hash + Clone,
+    {
+        let addresses = self.addresses(data)?;
+        let class_weights = HashMap::new();
+        let election = Election {
+            bleach: self.bleach,
+            bleach_policy: &BleachPolicy::Fixed,
+            tie_breaker: &TieBreaker::Label,
+            normalization: &Normalization::None,
+            class_weights: &class_weights,
+            reject_rule: &RejectRule::default(),
+        };
+        elect(
+            &self.discs,
+            &addresses,
+            &election,
+            self.number_of_hashtables,
+        )
+    }
+
+    pub fn save(&self) -> Result<Vec<u8>, WisardError>
+    where
+        T: TypeTag,
+        L: Serialize + Eq + Hash + TypeTag,
+    {
+        let body =
+            bincode::serialize(&self).map_err(|error| WisardError::WisardSerialization(error))?;
+        let header = Header::new::<T, L>(MODEL_NAME, self.get_info(), &body);
+        format::encode(&header, &body)
+    }
+
+    pub fn load(&mut self, stream: &[u8]) -> Result<(), WisardError>
+    where
+        T: TypeTag,
+        L: DeserializeOwned + Eq + Hash + TypeTag,
+    {
+        let (header, body) = format::read_header(stream)?;
+        header.expect::<T, L>(MODEL_NAME)?;
+        let decoded: Wisard<T, L> =
+            bincode::deserialize(body).map_err(|error| WisardError::WisardMalformed(error))?;
+        decoded.validate()?;
+        *self = decoded;
+        Ok(())
+    }
+
+    // what with_params and train take for granted, so a file that doesn't hold
+    // fails to load instead of panicking later
+    fn validate(&self) -> Result<(), WisardError> {
+        let n = self.number_of_hashtables as usize;
+        if n == 0 || self.addr_length == 0 || self.addr_length > MAX_ADDR_LENGTH {
+            return Err(WisardError::WisardInvalidModel(
+                "a size is zero or addr_length doesn't fit in a u64 address",
+            ));
+        }
+        if !mapping::is_permutation(&self.mapping, n * self.addr_length as usize) {
+            return Err(WisardError::WisardInvalidModel(
+                "the mapping is not a permutation of the bits of the tuples",
+            ));
+        }
+        if self
+            .discs
+            .values()
+            .any(|d| !d.has_shape(self.number_of_hashtables, &RamKind::HashTable))
+        {
+            return Err(WisardError::WisardInvalidModel(
+                "a discriminator doesn't match the hashtables",
+            ));
+        }
+        self.binarizer.check()
+    }
+
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), WisardError>
+    where
+        T: TypeTag,
+        L: Serialize + Eq + Hash + TypeTag,
+    {
+        let mut file = File::create(path)?;
+        file.write_all(&self.save()?)?;
+        Ok(())
+    }
+
+    pub fn load_from_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), WisardError>
+    where
+        T: TypeTag,
+        L: DeserializeOwned + Eq + Hash + TypeTag,
+    {
+        let stream = std::fs::read(path)?;
+        self.load(&stream)
+    }
+
+    pub fn erase(&mut self) {
+        self.mapping.shuffle(&mut thread_rng());
+        self.discs = HashMap::new();
+    }
+}
+
+impl<T, L> Default for Wisard<T, L> {
+    fn default() -> Self {
+        Wisard::new()
+    }
+}
+
+#[cfg(test)]
+mod lib_tests {
+    use super::*;
+
+    #[test]
+    fn test_threshold() {
+        let mut bits = Vec::new();
+        let binarizer = Binarizer::Threshold(127.0);
+        binarizer.encode(12.0, &mut bits);
+        binarizer.encode(200.0, &mut bits);
+        assert_eq!(bits, vec![false, true]);
+    }
+
+    #[test]
+    fn test_thermometer() {
+        let binarizer = Binarizer::Thermometer {
+            min: 0.0,
+            max: 4.0,
+            bits: 3,
+        };
+        let mut bits = Vec::new();
+        binarizer.encode(0.0, &mut bits);
+        binarizer.encode(2.5, &mut bits);
+        binarizer.encode(4.0, &mut bits);
+        assert_eq!(
+            bits,
+            vec![false, false, false, true, true, false, true, true, true]
+        );
+    }
+
+    #[test]
+    fn test_circular_thermometer() {
+        let binarizer = Binarizer::CircularThermometer {
+            min: 0.0,
+            max: 4.0,
+            bits: 4,
+        };
+        let mut bits = Vec::new();
+        binarizer.encode(0.0, &mut bits);
+        assert_eq!(bits, vec![true, true, false, false]);
+        bits.clear();
+        binarizer.encode(3.5, &mut bits);
+        assert_eq!(bits, vec![true, false, false, true]);
+    }
+
+    #[test]
+    fn test_train_classify() {
+        let binarizer = Binarizer::Thermometer {
+            min: 0.0,
+            max: 10.0,
+            bits: 4,
+        };
+        let mut wis = Wisard::<u8, &str>::with_params(4, 4, 0, binarizer).unwrap();
+        wis.train(&[0, 1, 2, 3], "low").unwrap();
+        wis.train(&[9, 10, 8, 9], "high").unwrap();
+
+        assert_eq!(wis.classify(&[1, 0, 2, 2]).unwrap(), "low");
+        assert_eq!(wis.classify(&[10, 9, 9, 8]).unwrap(), "high");
+    }
+
+    #[test]
+    fn test_save_load() {
+        let mut wis = Wisard::<u8, u8>::with_params(1, 4, 0, Binarizer::Threshold(5.0)).unwrap();
+        wis.train(&[0, 1, 2, 3], 0).unwrap();
+        wis.train(&[9, 10, 8, 9], 1).unwrap();
+        let encoded = wis.save().unwrap();
+
+        let mut decoded = Wisard::<u8, u8>::new();
+        decoded.load(&encoded).unwrap();
+        assert_eq!(decoded.get_info(), (1, 4, 0));
+        assert_eq!(decoded.classify(&[10, 9, 9, 8]).unwrap(), 1);
+
+        let mut other = Wisard::<u8, String>::new();
+        assert!(matches!(
+            other.load(&encoded),
+            Err(WisardError::WisardTypeMismatch { .. })
+        ));
+        let mut dict = crate::dict_wisard::Wisard::<u8, u8>::new();
+        assert!(dict.load(&encoded).is_err());
+    }
+
+    #[test]
+    fn test_load_rejects_invalid_models() {
+        let base = || Wisard::<u8, u8>::with_params(2, 4, 0, Binarizer::Threshold(5.0)).unwrap();
+        let mut broken = Vec::new();
+        let mut zero = base();
+        zero.addr_length = 0;
+        broken.push(zero);
+        let mut wide = base();
+        wide.addr_length = 64;
+        broken.push(wide);
+        let mut short = base();
+        short.mapping.pop();
+        broken.push(short);
+        let mut past = base();
+        past.mapping[0] = 8;
+        broken.push(past);
+        let mut shape = base();
+        shape.discs.insert(1, Discriminator::new(3));
+        broken.push(shape);
+
+        for model in broken.iter() {
+            let mut decoded = Wisard::<u8, u8>::new();
+            assert!(matches!(
+                decoded.load(&model.save().unwrap()),
+                Err(WisardError::WisardInvalidModel(_))
+            ));
+            assert_eq!(decoded.get_info(), (28, 28, 0));
+        }
+    }
+
+    #[test]
+    fn test_out_of_bounds() {
+        let mut wis = Wisard::<u8>::with_params(4, 4, 0, Binarizer::Threshold(0.5)).unwrap();
+        assert!(matches!(
+            wis.train(&[0, 1, 2], "short".to_string()),
+            Err(WisardError::WisardInputTooShort {
+                expected: 16,
+                actual: 3
+            })
+        ));
+    }
+
+    #[test]
+    fn test_addr_length() {
+        for addr_length in [0, 65].iter() {
+            assert!(matches!(
+                Wisard::<u8>::with_params(4, *addr_length, 0, Binarizer::Threshold(0.5)),
+                Err(WisardError::WisardInvalidConfig(_))
+            ));
+        }
+        assert!(Wisard::<u8>::with_params(1, 63, 0, Binarizer::Threshold(0.5)).is_ok());
+    }
+
+    #[test]
+    fn test_all_ones_address() {
+        // 64 set bits would be the address that never votes
+        assert!(matches!(
+            Wisard::<u8>::with_params(1, 64, 0, Binarizer::Threshold(0.5)),
+            Err(WisardError::WisardInvalidConfig(_))
+        ));
+        let mut wis = Wisard::<u8, u8>::with_params(1, 63, 0, Binarizer::Threshold(0.5)).unwrap();
+        wis.train(&[1; 63], 1).unwrap();
+        let prediction = wis.classify_with_scores(&[1; 63]).unwrap();
+        assert_eq!(prediction.scores[0].votes, 1);
+    }
+
+    #[test]
+    fn test_invalid_binarizers() {
+        let binarizers = [
+            Binarizer::Thermometer {
+                min: 0.0,
+                max: 4.0,
+                bits: 0,
+            },
+            Binarizer::CircularThermometer {
+                min: 0.0,
+                max: 4.0,
+                bits: 0,
+            },
+            Binarizer::Thermometer {
+                min: 4.0,
+                max: 4.0,
+                bits: 3,
+            },
+            Binarizer::CircularThermometer {
+                min: 4.0,
+                max: 0.0,
+                bits: 3,
+            },
+            Binarizer::Thermometer {
+                min: 0.0,
+                max: f64::NAN,
+                bits: 3,
+            },
+        ];
+        for binarizer in binarizers.iter() {
+            assert!(matches!(
+                binarizer.check(),
+                Err(WisardError::WisardInvalidConfig(_))
+            ));
+            assert!(matches!(
+                Wisard::<u8>::with_params(4, 4, 0, binarizer.clone()),
+                Err(WisardError::WisardInvalidConfig(_))
+            ));
+        }
+        assert!(Binarizer::Threshold(0.5).check().is_ok());
+
+        // encoding with an unchecked binarizer doesn't panic
+        let mut bits = Vec::new();
+        binarizers[1].encode(2.0, &mut bits);
+        assert!(bits.is_empty());
+    }
+}
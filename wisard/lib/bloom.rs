@@ -0,0 +1,169 @@
+use serde::{Deserialize, Serialize};
+
+// splitmix64 finalizer, spreads the bits of an address over the whole word
+pub(crate) fn mix(key: u64) -> u64 {
+    let mut z = key.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+// double hashing: the i-th hash is h1 + i * h2
+fn positions(key: u64, hashes: u16, size: usize) -> impl Iterator<Item = usize> {
+    let h = mix(key);
+    let h1 = h & 0xffff_ffff;
+    let h2 = (h >> 32) | 1;
+    (0..hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % size as u64) as usize)
+}
+
+// a RAM that only remembers if an address was ever written, bleaching can't be
+// applied to it because every hit counts as one
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    size: usize,
+    hashes: u16,
+}
+
+impl BloomFilter {
+    pub fn new(size: usize, hashes: u16) -> BloomFilter {
+        let size = size.max(1);
+        BloomFilter {
+            bits: vec![0; (size - 1) / 64 + 1],
+            size,
+            hashes: hashes.max(1),
+        }
+    }
+
+    // whether this is what new(size, hashes) builds, for filters that were loaded
+    pub(crate) fn has_shape(&self, size: usize, hashes: u16) -> bool {
+        self.size == size.max(1)
+            && self.bits.len() == (self.size - 1) / 64 + 1
+            && self.hashes == hashes.max(1)
+    }
+
+    pub fn insert(&mut self, key: u64) {
+        for p in positions(key, self.hashes, self.size) {
+            self.bits[p / 64] |= 1 << (p % 64);
+        }
+    }
+
+    // every key of other ends up in self, both must have the same size and hashes
+    pub fn merge(&mut self, other: &BloomFilter) {
+        for (bits, other_bits) in self.bits.iter_mut().zip(&other.bits) {
+            *bits |= other_bits;
+        }
+    }
+
+    pub fn count(&self, key: u64) -> u16 {
+        let hit = positions(key, self.hashes, self.size)
+            .all(|p| self.bits[p / 64] & (1 << (p % 64)) != 0);
+        hit as u16
+    }
+
+    // share of the bits that are set
+    pub fn fill_ratio(&self) -> f64 {
+        let set: u32 = self.bits.iter().map(|b| b.count_ones()).sum();
+        set as f64 / self.size as f64
+    }
+}
+
+// a RAM that keeps a saturating counter per slot, the count of an address is the
+// smallest of its counters, which never underestimates the real count
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct CountingBloomFilter {
+    counters: Vec<u16>,
+    hashes: u16,
+}
+
+impl CountingBloomFilter {
+    pub fn new(size: usize, hashes: u16) -> CountingBloomFilter {
+        CountingBloomFilter {
+            counters: vec![0; size.max(1)],
+            hashes: hashes.max(1),
+        }
+    }
+
+    pub(crate) fn has_shape(&self, size: usize, hashes: u16) -> bool {
+        self.counters.len() == size.max(1) && self.hashes == hashes.max(1)
+    }
+
+    pub fn insert(&mut self, key: u64) {
+        for p in positions(key, self.hashes, self.counters.len()) {
+            self.counters[p] = self.counters[p].saturating_add(1);
+        }
+    }
+
+    // adds the counts of other, both must have the same size and hashes
+    pub fn merge(&mut self, other: &CountingBloomFilter) {
+        for (counter, other_counter) in self.counters.iter_mut().zip(&other.counters) {
+            *counter = counter.saturating_add(*other_counter);
+        }
+    }
+
+    // undoes an insert, only if the key may have been inserted before
+    pub fn remove(&mut self, key: u64) {
+        if self.count(key) == 0 {
+            return;
+        }
+        for p in positions(key, self.hashes, self.counters.len()) {
+            self.counters[p] -= 1;
+        }
+    }
+
+    pub fn count(&self, key: u64) -> u16 {
+        positions(key, self.hashes, self.counters.len())
+            .map(|p| self.counters[p])
+            .min()
+            .unwrap_or(0)
+    }
+
+    // share of the counters above 0
+    pub fn fill_ratio(&self) -> f64 {
+        let set = self.counters.iter().filter(|&&c| c > 0).count();
+        set as f64 / self.counters.len() as f64
+    }
+}
+
+#[cfg(test)]
+mod lib_tests {
+    use super::*;
+
+    #[test]
+    fn test_bloom_membership() {
+        let mut filter = BloomFilter::new(1024, 3);
+        filter.insert(42);
+        filter.insert(7);
+        assert_eq!(filter.count(42), 1);
+        assert_eq!(filter.count(7), 1);
+        assert_eq!(filter.count(1337), 0);
+    }
+
+    #[test]
+    fn test_counting_bloom_counts() {
+        let mut filter = CountingBloomFilter::new(1024, 3);
+        filter.insert(42);
+        filter.insert(42);
+        filter.insert(7);
+        assert_eq!(filter.count(42), 2);
+        assert_eq!(filter.count(7), 1);
+        assert_eq!(filter.count(1337), 0);
+
+        filter.remove(42);
+        filter.remove(1337);
+        assert_eq!(filter.count(42), 1);
+        assert_eq!(filter.count(7), 1);
+    }
+
+    #[test]
+    fn test_fill_ratio() {
+        let mut filter = BloomFilter::new(100, 1);
+        let mut counting = CountingBloomFilter::new(100, 1);
+        assert_eq!(filter.fill_ratio(), 0.0);
+        filter.insert(42);
+        counting.insert(42);
+        counting.insert(42);
+        assert_eq!(filter.fill_ratio(), 0.01);
+        assert_eq!(counting.fill_ratio(), 0.01);
+    }
+}
@@ -0,0 +1,461 @@
+use crate::dict_wisard::{
+    AddressMode, BleachPolicy, LoadLimits, Normalization, RamKind, RejectRule, RemainderPolicy,
+    TieBreaker,
+};
+use crate::errors::WisardError;
+use crate::mapping::{self, MappingStrategy};
+use serde::{Deserialize, Serialize};
+
+// a config can come from a request, so it can't ask for more than a model
+// loaded with the default LoadLimits could hold
+const MAX_INPUT_LENGTH: usize = 10_000_000;
+const MAX_BLOOM_HASHES: u16 = 64;
+
+// everything needed to build a dict_wisard model, checked as a whole before
+// the model exists. Missing fields take the values of default, the MNIST
+// model of Wisard::new
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct WisardConfig {
+    input_length: Option<usize>,
+    addr_length: u16,
+    // derived from input_length and addr_length when not set
+    number_of_hashtables: Option<u16>,
+    bleach: u16,
+    bleach_policy: BleachPolicy,
+    tie_breaker: TieBreaker,
+    normalization: Normalization,
+    reject: RejectRule,
+    seed: Option<u64>,
+    mapping: MappingStrategy,
+    address_mode: AddressMode,
+    ram: RamKind,
+    // required when the input length isn't a multiple of addr_length
+    remainder: Option<RemainderPolicy>,
+}
+
+impl Default for WisardConfig {
+    fn default() -> Self {
+        WisardConfig {
+            input_length: Some(784),
+            addr_length: 28,
+            number_of_hashtables: None,
+            bleach: 0,
+            bleach_policy: BleachPolicy::Fixed,
+            tie_breaker: TieBreaker::Label,
+            normalization: Normalization::None,
+            reject: RejectRule::default(),
+            seed: None,
+            mapping: MappingStrategy::Random,
+            address_mode: AddressMode::RankTable,
+            ram: RamKind::HashTable,
+            remainder: None,
+        }
+    }
+}
+
+impl WisardConfig {
+    pub fn new() -> Self {
+        WisardConfig::default()
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, WisardError> {
+        serde_json::from_str(json).map_err(|error| WisardError::WisardMalformed(Box::new(error)))
+    }
+
+    pub fn from_toml(toml: &str) -> Result<Self, WisardError> {
+        toml::from_str(toml).map_err(|error| WisardError::WisardMalformed(Box::new(error)))
+    }
+
+    pub fn input_length(mut self, input_length: usize) -> Self {
+        self.input_length = Some(input_length);
+        self
+    }
+
+    // the number of RAMs can be set instead of an input length, or on top of
+    // it to double check it
+    pub fn without_input_length(mut self) -> Self {
+        self.input_length = None;
+        self
+    }
+
+    pub fn addr_length(mut self, addr_length: u16) -> Self {
+        self.addr_length = addr_length;
+        self
+    }
+
+    pub fn number_of_hashtables(mut self, number_of_hashtables: u16) -> Self {
+        self.number_of_hashtables = Some(number_of_hashtables);
+        self
+    }
+
+    pub fn bleach(mut self, bleach: u16) -> Self {
+        self.bleach = bleach;
+        self
+    }
+
+    pub fn bleach_policy(mut self, bleach_policy: BleachPolicy) -> Self {
+        self.bleach_policy = bleach_policy;
+        self
+    }
+
+    pub fn tie_breaker(mut self, tie_breaker: TieBreaker) -> Self {
+        self.tie_breaker = tie_breaker;
+        self
+    }
+
+    pub fn normalization(mut self, normalization: Normalization) -> Self {
+        self.normalization = normalization;
+        self
+    }
+
+    pub fn reject(mut self, reject: RejectRule) -> Self {
+        self.reject = reject;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn mapping(mut self, mapping: MappingStrategy) -> Self {
+        self.mapping = mapping;
+        self
+    }
+
+    pub fn address_mode(mut self, address_mode: AddressMode) -> Self {
+        self.address_mode = address_mode;
+        self
+    }
+
+    pub fn ram(mut self, ram: RamKind) -> Self {
+        self.ram = ram;
+        self
+    }
+
+    pub fn remainder(mut self, remainder: RemainderPolicy) -> Self {
+        self.remainder = Some(remainder);
+        self
+    }
+
+    pub fn get_input_length(&self) -> Option<usize> {
+        self.input_length
+    }
+
+    pub fn get_addr_length(&self) -> u16 {
+        self.addr_length
+    }
+
+    pub fn get_bleach(&self) -> u16 {
+        self.bleach
+    }
+
+    pub fn get_bleach_policy(&self) -> &BleachPolicy {
+        &self.bleach_policy
+    }
+
+    pub fn get_tie_breaker(&self) -> &TieBreaker {
+        &self.tie_breaker
+    }
+
+    pub fn get_normalization(&self) -> &Normalization {
+        &self.normalization
+    }
+
+    pub fn get_reject_rule(&self) -> &RejectRule {
+        &self.reject
+    }
+
+    pub fn get_seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    pub fn get_mapping(&self) -> &MappingStrategy {
+        &self.mapping
+    }
+
+    pub fn get_address_mode(&self) -> &AddressMode {
+        &self.address_mode
+    }
+
+    pub fn get_ram(&self) -> &RamKind {
+        &self.ram
+    }
+
+    pub fn get_remainder(&self) -> &Option<RemainderPolicy> {
+        &self.remainder
+    }
+
+    // the number of RAMs the model will have, or why there's no way to build it
+    pub fn get_number_of_hashtables(&self) -> Result<u16, WisardError> {
+        let a = self.addr_length as usize;
+        if a == 0 {
+            return Err(invalid("the tuple size (addr_length) must be at least 1"));
+        }
+        let number_of_hashtables = match (self.input_length, self.number_of_hashtables) {
+            (None, None) => {
+                return Err(invalid(
+                    "set the input length, the number of hashtables or both",
+                ))
+            }
+            (None, Some(n)) => n,
+            (Some(input_length), explicit) => {
+                let derived = match &self.remainder {
+                    Some(remainder) => remainder.number_of_hashtables(input_length, a),
+                    None if input_length % a == 0 => input_length / a,
+                    None => {
+                        return Err(invalid(format!(
+                            "{} inputs don't split into tuples of {}, set a remainder policy",
+                            input_length, a
+                        )))
+                    }
+                };
+                if derived > u16::MAX as usize {
+                    return Err(invalid(format!(
+                        "{} inputs in tuples of {} need {} hashtables, more than the {} allowed",
+                        input_length,
+                        a,
+                        derived,
+                        u16::MAX
+                    )));
+                }
+                match explicit {
+                    Some(n) if n as usize != derived => {
+                        return Err(invalid(format!(
+                            "{} inputs in tuples of {} need {} hashtables, not {}",
+                            input_length, a, derived, n
+                        )))
+                    }
+                    _ => derived as u16,
+                }
+            }
+        };
+        if number_of_hashtables == 0 {
+            return Err(invalid("the model needs at least 1 hashtable"));
+        }
+        Ok(number_of_hashtables)
+    }
+
+    pub fn validate(&self) -> Result<(), WisardError> {
+        let n = self.get_number_of_hashtables()? as usize;
+        let a = self.addr_length as usize;
+        let input_length = self.input_length.unwrap_or(n * a);
+        if input_length > MAX_INPUT_LENGTH {
+            return Err(invalid(format!(
+                "an input of {} values is more than the {} a model takes",
+                input_length, MAX_INPUT_LENGTH
+            )));
+        }
+        self.mapping.check(input_length, n, a).map_err(invalid)?;
+        if let MappingStrategy::Custom(custom) = &self.mapping {
+            let remainder = self.remainder.clone().unwrap_or(RemainderPolicy::Drop);
+            if let Err(WisardError::WisardInvalidModel(reason)) =
+                mapping::check_mapping(custom, input_length, n, a, &remainder)
+            {
+                return Err(invalid(format!(
+                    "the custom mapping doesn't fit, {}",
+                    reason
+                )));
+            }
+        }
+        self.reject.check()?;
+        match self.ram {
+            RamKind::Bloom { size, hashes } | RamKind::CountingBloom { size, hashes } => {
+                // the RAMs of a discriminator are allocated as soon as it's trained
+                let max_size = LoadLimits::default().max_ram_entries / n.max(1);
+                if size > max_size {
+                    return Err(invalid(format!(
+                        "bloom RAMs of size {} are too big, {} RAMs of a discriminator can take up to {} each",
+                        size, n, max_size
+                    )));
+                }
+                if hashes > MAX_BLOOM_HASHES {
+                    return Err(invalid(format!(
+                        "bloom RAMs take up to {} hashes, not {}",
+                        MAX_BLOOM_HASHES, hashes
+                    )));
+                }
+            }
+            RamKind::HashTable => (),
+        }
+        if let RamKind::Bloom { .. } = self.ram {
+            if self.bleach > 0 {
+                return Err(invalid(
+                    "bloom RAMs only count to 1, so any bleach silences them",
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn invalid<S: Into<String>>(reason: S) -> WisardError {
+    WisardError::WisardInvalidConfig(reason.into())
+}
+
+#[cfg(test)]
+mod lib_tests {
+    use super::*;
+
+    #[test]
+    fn test_derived_hashtables() {
+        let config = WisardConfig::new();
+        assert_eq!(config.get_number_of_hashtables().unwrap(), 28);
+
+        let config = WisardConfig::new().input_length(784).addr_length(16);
+        assert_eq!(config.get_number_of_hashtables().unwrap(), 49);
+
+        let config = WisardConfig::new()
+            .without_input_length()
+            .addr_length(3)
+            .number_of_hashtables(5);
+        assert_eq!(config.get_number_of_hashtables().unwrap(), 5);
+
+        let config = WisardConfig::new().addr_length(10);
+        let counts = [
+            (RemainderPolicy::Pad, 79),
+            (RemainderPolicy::Wrap, 79),
+            (RemainderPolicy::Drop, 78),
+            (RemainderPolicy::Shorter, 79),
+        ];
+        for (remainder, count) in counts.iter() {
+            let config = config.clone().remainder(remainder.clone());
+            assert_eq!(config.get_number_of_hashtables().unwrap(), *count);
+        }
+    }
+
+    #[test]
+    fn test_impossible_configs() {
+        let configs = [
+            WisardConfig::new().addr_length(0),
+            WisardConfig::new().addr_length(5),
+            WisardConfig::new().number_of_hashtables(10),
+            WisardConfig::new().without_input_length(),
+            WisardConfig::new().input_length(0),
+            WisardConfig::new()
+                .input_length(5)
+                .addr_length(10)
+                .remainder(RemainderPolicy::Drop),
+            WisardConfig::new().mapping(MappingStrategy::Patches {
+                width: 28,
+                height: 27,
+                patch_width: 4,
+                patch_height: 7,
+            }),
+            WisardConfig::new().mapping(MappingStrategy::Patches {
+                width: 28,
+                height: 28,
+                patch_width: 3,
+                patch_height: 4,
+            }),
+            WisardConfig::new()
+                .input_length(7)
+                .addr_length(3)
+                .remainder(RemainderPolicy::Pad)
+                .mapping(MappingStrategy::Striped),
+            WisardConfig::new()
+                .input_length(4)
+                .addr_length(2)
+                .mapping(MappingStrategy::Custom(vec![0, 1, 2, 2])),
+            WisardConfig::new().bleach(1).ram(RamKind::Bloom {
+                size: 1024,
+                hashes: 3,
+            }),
+        ];
+        for config in configs.iter() {
+            assert!(matches!(
+                config.validate(),
+                Err(WisardError::WisardInvalidConfig(_))
+            ));
+        }
+        match WisardConfig::new().addr_length(5).validate() {
+            Err(error) => assert_eq!(
+                error.to_string(),
+                "Invalid configuration: 784 inputs don't split into tuples of 5, set a remainder policy"
+            ),
+            Ok(_) => panic!("784 inputs were split into tuples of 5"),
+        }
+    }
+
+    #[test]
+    fn test_upper_bounds() {
+        let too_big = [
+            WisardConfig::new()
+                .input_length(MAX_INPUT_LENGTH + 1)
+                .addr_length(200)
+                .remainder(RemainderPolicy::Pad),
+            WisardConfig::new()
+                .without_input_length()
+                .addr_length(u16::MAX)
+                .number_of_hashtables(u16::MAX),
+            WisardConfig::new().ram(RamKind::Bloom {
+                size: usize::MAX,
+                hashes: 3,
+            }),
+            WisardConfig::new().ram(RamKind::CountingBloom {
+                size: 1024,
+                hashes: MAX_BLOOM_HASHES + 1,
+            }),
+        ];
+        let reasons = [
+            "values is more than",
+            "values is more than",
+            "too big",
+            "hashes",
+        ];
+        for (config, reason) in too_big.iter().zip(reasons.iter()) {
+            match config.validate() {
+                Err(WisardError::WisardInvalidConfig(message)) => {
+                    assert!(message.contains(reason), "{}", message)
+                }
+                other => panic!("{:?} was accepted: {:?}", config, other),
+            }
+        }
+
+        let largest = WisardConfig::new().ram(RamKind::CountingBloom {
+            size: LoadLimits::default().max_ram_entries / 28,
+            hashes: MAX_BLOOM_HASHES,
+        });
+        assert!(largest.validate().is_ok());
+    }
+
+    #[test]
+    fn test_parse() {
+        let json = r#"{"input_length": 12, "addr_length": 3, "seed": 7, "address_mode": "Lehmer"}"#;
+        let config = WisardConfig::from_json(json).unwrap();
+        assert_eq!(config.get_number_of_hashtables().unwrap(), 4);
+        assert_eq!(config.get_seed(), Some(7));
+        assert_eq!(config.get_address_mode(), &AddressMode::Lehmer);
+        assert_eq!(config.get_bleach(), 0);
+
+        let toml = r#"
+            input_length = 12
+            addr_length = 3
+            seed = 7
+            address_mode = "Lehmer"
+        "#;
+        assert_eq!(WisardConfig::from_toml(toml).unwrap(), config);
+
+        let toml = r#"
+            addr_length = 16
+            mapping = { Patches = { width = 28, height = 28, patch_width = 4, patch_height = 4 } }
+        "#;
+        let config = WisardConfig::from_toml(toml).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(
+            config.get_mapping(),
+            &MappingStrategy::Patches {
+                width: 28,
+                height: 28,
+                patch_width: 4,
+                patch_height: 4
+            }
+        );
+
+        assert!(matches!(
+            WisardConfig::from_json(r#"{"tuple_size": 3}"#),
+            Err(WisardError::WisardMalformed(_))
+        ));
+    }
+}
@@ -1,278 +1,2403 @@
+use crate::bloom::{mix, BloomFilter, CountingBloomFilter};
+use crate::config::WisardConfig;
 use crate::errors::WisardError;
-use rand::seq::SliceRandom;
-use rand::thread_rng;
+use crate::format::{self, ExportFormat, Header, TypeTag};
+use crate::mapping::{self, MappingDocument, MappingStrategy};
+use bincode::Options;
+use rayon::prelude::*;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
+use std::hash::Hash;
+use std::io::Write;
 use std::marker::PhantomData;
 use std::path::Path;
 
+// storage used by the RAMs of every discriminator of a model
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub enum RamKind {
+    // one hashmap per RAM, grows with the number of distinct addresses seen
+    HashTable,
+    // fixed size RAMs that only remember if an address was written, so any
+    // bleach above 0 silences them
+    Bloom { size: usize, hashes: u16 },
+    // fixed size RAMs with counters, bleaching works as with HashTable
+    CountingBloom { size: usize, hashes: u16 },
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+enum Rams {
+    HashTable(Vec<HashMap<u64, u16>>),
+    Bloom(Vec<BloomFilter>),
+    CountingBloom(Vec<CountingBloomFilter>),
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct Discriminator {
     number_of_hashtables: u16,
-    h_rams: Vec<HashMap<u64, u16>>,
+    h_rams: Rams,
     times_trained: u64,
 }
 
 impl Discriminator {
     pub fn new(num: u16) -> Discriminator {
+        Discriminator::with_ram(num, &RamKind::HashTable)
+    }
+
+    pub fn with_ram(num: u16, ram: &RamKind) -> Discriminator {
+        let h_rams = match *ram {
+            RamKind::HashTable => Rams::HashTable(vec![HashMap::new(); num as usize]),
+            RamKind::Bloom { size, hashes } => {
+                Rams::Bloom(vec![BloomFilter::new(size, hashes); num as usize])
+            }
+            RamKind::CountingBloom { size, hashes } => {
+                Rams::CountingBloom(vec![CountingBloomFilter::new(size, hashes); num as usize])
+            }
+        };
         Discriminator {
             number_of_hashtables: num,
-            h_rams: vec![HashMap::new(); num as usize],
+            h_rams,
             times_trained: 0,
         }
     }
 
-    pub fn train(&mut self, x: Vec<u64>) -> Result<(), WisardError> {
-        for i in 0..self.number_of_hashtables {
-            let key = x[i as usize];
-            let counter = self
-                .h_rams
-                .get_mut(i as usize)
-                .ok_or_else(|| WisardError::WisardOutOfBounds)?
-                .entry(key)
-                .or_insert(0);
-            *counter += 1;
+    pub fn train(&mut self, x: &[u64]) -> Result<(), WisardError> {
+        let n = self.number_of_hashtables as usize;
+        if x.len() < n {
+            return Err(WisardError::WisardInputTooShort {
+                expected: n,
+                actual: x.len(),
+            });
+        }
+        for (i, &key) in x
+            .iter()
+            .enumerate()
+            .take(self.number_of_hashtables as usize)
+        {
+            match &mut self.h_rams {
+                Rams::HashTable(rams) => {
+                    let counter = rams
+                        .get_mut(i)
+                        .ok_or(WisardError::WisardInvalidModel(MISSING_RAM))?
+                        .entry(key)
+                        .or_insert(0);
+                    *counter += 1;
+                }
+                Rams::Bloom(rams) => rams
+                    .get_mut(i)
+                    .ok_or(WisardError::WisardInvalidModel(MISSING_RAM))?
+                    .insert(key),
+                Rams::CountingBloom(rams) => rams
+                    .get_mut(i)
+                    .ok_or(WisardError::WisardInvalidModel(MISSING_RAM))?
+                    .insert(key),
+            }
         }
         self.times_trained += 1;
         Ok(())
     }
 
-    pub fn classify(&self, x: &Vec<u64>, bleach: u16) -> (u64, u64) {
-        let mut votes: u64 = 0;
-        for i in 0..self.number_of_hashtables {
-            let key = x[i as usize];
-            if let Some(x) = self.h_rams[i as usize].get(&key) {
-                if *x > bleach {
-                    votes += 1
-                };
+    // undoes a train with the same addresses, leaving the discriminator as it
+    // was if any of them was never written
+    pub fn untrain(&mut self, x: &[u64]) -> Result<(), WisardError> {
+        let n = self.number_of_hashtables as usize;
+        if x.len() < n {
+            return Err(WisardError::WisardInputTooShort {
+                expected: n,
+                actual: x.len(),
+            });
+        }
+        if let Rams::Bloom(_) = self.h_rams {
+            return Err(WisardError::WisardUnsupported);
+        }
+        if self.times_trained == 0
+            || x.iter()
+                .take(n)
+                .enumerate()
+                .any(|(i, &k)| self.count(i, k) == 0)
+        {
+            return Err(WisardError::WisardNotTrained);
+        }
+        for (i, &key) in x.iter().enumerate().take(n) {
+            match &mut self.h_rams {
+                Rams::HashTable(rams) => {
+                    let counter = rams[i].get_mut(&key).unwrap();
+                    *counter -= 1;
+                    if *counter == 0 {
+                        rams[i].remove(&key);
+                    }
+                }
+                Rams::Bloom(_) => unreachable!(),
+                Rams::CountingBloom(rams) => rams[i].remove(key),
             }
         }
+        self.times_trained -= 1;
+        Ok(())
+    }
+
+    // adds up the training of other, whose addresses are renamed through
+    // translation when given, bloom filters can't rename what they hold
+    pub fn merge(
+        &mut self,
+        other: &Discriminator,
+        translation: Option<&HashMap<u64, u64>>,
+    ) -> Result<(), WisardError> {
+        if self.number_of_hashtables != other.number_of_hashtables {
+            return Err(WisardError::WisardIncompatible);
+        }
+        let rename = |key: u64| match translation {
+            Some(translation) => *translation.get(&key).unwrap_or(&key),
+            None => key,
+        };
+        match (&mut self.h_rams, &other.h_rams) {
+            (Rams::HashTable(rams), Rams::HashTable(other_rams)) => {
+                for (ram, other_ram) in rams.iter_mut().zip(other_rams) {
+                    for (&key, &count) in other_ram {
+                        let counter = ram.entry(rename(key)).or_insert(0);
+                        *counter = counter.saturating_add(count);
+                    }
+                }
+            }
+            (Rams::Bloom(rams), Rams::Bloom(other_rams)) if translation.is_none() => {
+                for (ram, other_ram) in rams.iter_mut().zip(other_rams) {
+                    ram.merge(other_ram);
+                }
+            }
+            (Rams::CountingBloom(rams), Rams::CountingBloom(other_rams))
+                if translation.is_none() =>
+            {
+                for (ram, other_ram) in rams.iter_mut().zip(other_rams) {
+                    ram.merge(other_ram);
+                }
+            }
+            (Rams::Bloom(_), Rams::Bloom(_)) | (Rams::CountingBloom(_), Rams::CountingBloom(_)) => {
+                return Err(WisardError::WisardUnsupported)
+            }
+            _ => return Err(WisardError::WisardIncompatible),
+        }
+        self.times_trained += other.times_trained;
+        Ok(())
+    }
+
+    pub fn to_document(&self) -> DiscriminatorDocument {
+        let rams = match &self.h_rams {
+            Rams::HashTable(rams) => RamsDocument::HashTable(
+                rams.iter()
+                    .map(|ram| {
+                        let mut entries: Vec<(u64, u16)> =
+                            ram.iter().map(|(&k, &v)| (k, v)).collect();
+                        entries.sort_unstable();
+                        entries
+                    })
+                    .collect(),
+            ),
+            Rams::Bloom(rams) => RamsDocument::Bloom(rams.clone()),
+            Rams::CountingBloom(rams) => RamsDocument::CountingBloom(rams.clone()),
+        };
+        DiscriminatorDocument {
+            number_of_hashtables: self.number_of_hashtables,
+            times_trained: self.times_trained,
+            rams,
+        }
+    }
+
+    pub fn from_document(document: DiscriminatorDocument) -> Discriminator {
+        let h_rams = match document.rams {
+            RamsDocument::HashTable(rams) => Rams::HashTable(
+                rams.into_iter()
+                    .map(|entries| entries.into_iter().collect())
+                    .collect(),
+            ),
+            RamsDocument::Bloom(rams) => Rams::Bloom(rams),
+            RamsDocument::CountingBloom(rams) => Rams::CountingBloom(rams),
+        };
+        Discriminator {
+            number_of_hashtables: document.number_of_hashtables,
+            h_rams,
+            times_trained: document.times_trained,
+        }
+    }
+
+    pub fn export(&self, format: &ExportFormat) -> Result<Vec<u8>, WisardError> {
+        format.encode(&self.to_document())
+    }
+
+    pub fn import(stream: &[u8], format: &ExportFormat) -> Result<Discriminator, WisardError> {
+        let document: DiscriminatorDocument = format.decode(stream)?;
+        Ok(Discriminator::from_document(document))
+    }
+
+    // whether the RAMs are the ones with_ram(num, ram) builds
+    pub(crate) fn has_shape(&self, num: u16, ram: &RamKind) -> bool {
+        if self.number_of_hashtables != num {
+            return false;
+        }
+        match (&self.h_rams, ram) {
+            (Rams::HashTable(rams), RamKind::HashTable) => rams.len() == num as usize,
+            (Rams::Bloom(rams), RamKind::Bloom { size, hashes }) => {
+                rams.len() == num as usize && rams.iter().all(|r| r.has_shape(*size, *hashes))
+            }
+            (Rams::CountingBloom(rams), RamKind::CountingBloom { size, hashes }) => {
+                rams.len() == num as usize && rams.iter().all(|r| r.has_shape(*size, *hashes))
+            }
+            _ => false,
+        }
+    }
+
+    // addresses held by the hashtables, bloom filters have a fixed size
+    fn ram_entries(&self) -> usize {
+        match &self.h_rams {
+            Rams::HashTable(rams) => rams.iter().map(|r| r.len()).sum(),
+            _ => 0,
+        }
+    }
+
+    pub fn get_times_trained(&self) -> u64 {
+        self.times_trained
+    }
+
+    // how full the RAMs are on average, in [0, 1]. A hashtable is full when
+    // every sample wrote a new address, a bloom filter when every slot is set
+    pub fn fill_ratio(&self) -> f64 {
+        let n = self.number_of_hashtables as f64;
+        match &self.h_rams {
+            Rams::HashTable(_) if self.times_trained == 0 => 0.0,
+            Rams::HashTable(_) => self.ram_entries() as f64 / (n * self.times_trained as f64),
+            Rams::Bloom(rams) => rams.iter().map(|r| r.fill_ratio()).sum::<f64>() / n,
+            Rams::CountingBloom(rams) => rams.iter().map(|r| r.fill_ratio()).sum::<f64>() / n,
+        }
+    }
+
+    fn count(&self, i: usize, key: u64) -> u16 {
+        match &self.h_rams {
+            Rams::HashTable(rams) => *rams[i].get(&key).unwrap_or(&0),
+            Rams::Bloom(rams) => rams[i].count(key),
+            Rams::CountingBloom(rams) => rams[i].count(key),
+        }
+    }
+
+    // what each RAM holds at its address, 0 for the addresses that don't vote
+    fn counts(&self, x: &[u64]) -> Vec<u16> {
+        x.iter()
+            .enumerate()
+            .take(self.number_of_hashtables as usize)
+            .map(|(i, &key)| {
+                if key == NO_VOTE {
+                    0
+                } else {
+                    self.count(i, key)
+                }
+            })
+            .collect()
+    }
+
+    pub fn classify(&self, x: &[u64], bleach: u16) -> (u64, u64) {
+        let mut votes: u64 = 0;
+        for (i, &key) in x
+            .iter()
+            .enumerate()
+            .take(self.number_of_hashtables as usize)
+        {
+            if key != NO_VOTE && self.count(i, key) > bleach {
+                votes += 1
+            };
+        }
         (votes, self.times_trained)
     }
 }
 
+// response of a single discriminator to a sample
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Score<L = String> {
+    pub label: L,
+    pub votes: u64,
+    // votes divided by the number of hashtables, in [0, 1], then normalized
+    // and weighted when the model says so
+    pub score: f64,
+    pub times_trained: u64,
+}
+
+// full result of a classification, scores are sorted from best to worst
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Prediction<L = String> {
+    pub label: L,
+    // normalized score difference between first and second place
+    pub margin: f64,
+    // the threshold the votes were counted with
+    pub bleach: u16,
+    pub outcome: Outcome<L>,
+    pub scores: Vec<Score<L>>,
+}
+
+// what a classification ends with
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub enum Outcome<L = String> {
+    // one class won, after breaking any tie
+    Label(L),
+    // classes tied for first place, sorted, and the tie breaker is Ambiguous
+    Ambiguous(Vec<L>),
+    // the best class isn't confident enough for the reject rule, label is
+    // only a guess
+    Rejected,
+}
+
+impl<L> Prediction<L> {
+    // the label that won, an error for an ambiguous or rejected outcome
+    pub fn into_label(self) -> Result<L, WisardError> {
+        match self.outcome {
+            Outcome::Label(label) => Ok(label),
+            Outcome::Ambiguous(labels) => Err(WisardError::WisardAmbiguous(labels.len())),
+            Outcome::Rejected => Err(WisardError::WisardRejected),
+        }
+    }
+}
+
+// when classify refuses to pick a label, both conditions apply when both are
+// set. The default rejects nothing
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct RejectRule {
+    // votes the best class needs at least
+    pub min_votes: u64,
+    // how far ahead of the second the best class has to be, relative to its
+    // own score, in [0, 1]
+    pub min_margin: f64,
+}
+
+impl RejectRule {
+    pub fn check(&self) -> Result<(), WisardError> {
+        if !(0.0..=1.0).contains(&self.min_margin) {
+            return Err(WisardError::WisardInvalidConfig(format!(
+                "the minimum margin must be between 0 and 1, not {}",
+                self.min_margin
+            )));
+        }
+        Ok(())
+    }
+
+    fn rejects<L>(&self, scores: &[Score<L>]) -> bool {
+        let best = match scores.first() {
+            Some(best) => best,
+            None => return true,
+        };
+        let second = scores.get(1).map_or(0.0, |s| s.score);
+        let margin = if best.score > 0.0 {
+            (best.score - second) / best.score
+        } else {
+            0.0
+        };
+        best.votes < self.min_votes || margin < self.min_margin
+    }
+}
+
+// threshold of the labels classify_multi hasn't been given or calibrated one
+pub const DEFAULT_LABEL_THRESHOLD: f64 = 0.5;
+
+// the threshold that gets the best F1 on (score, tagged) pairs, a label is
+// predicted when its score is above it. Thresholds sit halfway between the
+// scores seen, and the highest one wins a tie, so a label never tagged is
+// never predicted on this data
+fn best_threshold(pairs: &mut [(f64, bool)]) -> f64 {
+    pairs.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal));
+    let tagged = pairs.iter().filter(|&&(_, t)| t).count() as f64;
+    let mut best = (0.0, pairs.first().map_or(DEFAULT_LABEL_THRESHOLD, |p| p.0));
+    let (mut tp, mut fp) = (0.0, 0.0);
+    let mut i = 0;
+    while i < pairs.len() {
+        let score = pairs[i].0;
+        while i < pairs.len() && pairs[i].0 == score {
+            if pairs[i].1 {
+                tp += 1.0;
+            } else {
+                fp += 1.0;
+            }
+            i += 1;
+        }
+        let below = pairs.get(i).map_or(0.0, |p| p.0);
+        let f1 = 2.0 * tp / (tp + fp + tagged);
+        if f1 > best.0 {
+            best = (f1, (score + below) / 2.0);
+        }
+    }
+    best.1
+}
+
+// how classify picks between classes with the same votes, after dynamic
+// bleaching if there is any
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub enum TieBreaker {
+    // the smallest label
+    Label,
+    // the class trained with the most samples, then the smallest label
+    MostTrained,
+    // the class trained with the fewest samples, then the smallest label
+    LeastTrained,
+    // one of the tied classes at random, the same one for the same seed and
+    // sample
+    Random(u64),
+    // no class wins, the outcome lists the tied ones
+    Ambiguous,
+}
+
+// how the bleach threshold is picked at classification
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub enum BleachPolicy {
+    // always the bleach of the model
+    Fixed,
+    // starts at the bleach of the model and goes up one step at a time while
+    // the best classes tie, stopping before their votes drop to 0
+    Dynamic,
+}
+
+// how the votes of a class are scaled before classes are compared, so heavily
+// trained classes, whose RAMs saturate, don't win too often
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub enum Normalization {
+    // votes as they are
+    None,
+    // divided by the share of the training the class got, relative to an even
+    // share, so a balanced model keeps its scores
+    Prior,
+    // divided by 1 + ln(times_trained), a milder penalty than Prior
+    TimesTrained,
+    // divided by the fill ratio of the RAMs of the class
+    Fill,
+}
+
+impl Normalization {
+    // what the score of disc is multiplied by, out of total trainings over
+    // classes classes
+    fn factor(&self, disc: &Discriminator, total: u64, classes: usize) -> f64 {
+        let times_trained = disc.get_times_trained();
+        match self {
+            Normalization::None => 1.0,
+            Normalization::Prior if times_trained == 0 => 1.0,
+            Normalization::Prior => total as f64 / (classes as f64 * times_trained as f64),
+            Normalization::TimesTrained => 1.0 / (1.0 + (times_trained.max(1) as f64).ln()),
+            Normalization::Fill => match disc.fill_ratio() {
+                fill if fill > 0.0 => 1.0 / fill,
+                _ => 1.0,
+            },
+        }
+    }
+}
+
+// how elect turns the responses of the discriminators into a prediction
+pub(crate) struct Election<'a, L> {
+    pub bleach: u16,
+    pub bleach_policy: &'a BleachPolicy,
+    pub tie_breaker: &'a TieBreaker,
+    pub normalization: &'a Normalization,
+    // classes missing from it weigh 1
+    pub class_weights: &'a HashMap<L, f64>,
+    pub reject_rule: &'a RejectRule,
+}
+
+// response of a class: label, RAM counts, times trained and the factor its
+// score is multiplied by
+type Response<'a, L> = (&'a L, Vec<u16>, u64, f64);
+
+// votes and score of each class when RAMs need more than bleach to vote
+fn votes_at<L>(responses: &[Response<L>], bleach: u16) -> Vec<(u64, f64)> {
+    responses
+        .iter()
+        .map(|(_, counts, _, factor)| {
+            let votes = counts.iter().filter(|&&c| c > bleach).count() as u64;
+            (votes, votes as f64 * factor)
+        })
+        .collect()
+}
+
+fn is_tie(votes: &[(u64, f64)]) -> bool {
+    let best = votes.iter().map(|&(_, s)| s).fold(f64::MIN, f64::max);
+    votes.iter().filter(|&&(_, s)| s == best).count() > 1
+}
+
+// polls every discriminator and sorts their responses, shared by all models
+// built on top of Discriminator
+pub(crate) fn elect<L: Ord + Hash + Clone>(
+    discs: &HashMap<L, Discriminator>,
+    addresses: &[u64],
+    election: &Election<L>,
+    number_of_hashtables: u16,
+) -> Result<Prediction<L>, WisardError> {
+    let responses = respond(discs, addresses, election, number_of_hashtables);
+    decide(&responses, addresses, election)
+}
+
+fn respond<'a, L: Eq + Hash>(
+    discs: &'a HashMap<L, Discriminator>,
+    addresses: &[u64],
+    election: &Election<L>,
+    number_of_hashtables: u16,
+) -> Vec<Response<'a, L>> {
+    let total: u64 = discs.values().map(|d| d.get_times_trained()).sum();
+    discs
+        .iter()
+        .map(|(label, d)| {
+            let weight = election.class_weights.get(label).copied().unwrap_or(1.0);
+            let factor = weight * election.normalization.factor(d, total, discs.len())
+                / number_of_hashtables as f64;
+            (label, d.counts(addresses), d.get_times_trained(), factor)
+        })
+        .collect()
+}
+
+// the prediction out of the responses, at the bleach of election
+fn decide<L: Ord + Clone>(
+    responses: &[Response<L>],
+    addresses: &[u64],
+    election: &Election<L>,
+) -> Result<Prediction<L>, WisardError> {
+    let mut bleach = election.bleach;
+    let mut votes = votes_at(responses, bleach);
+    if *election.bleach_policy == BleachPolicy::Dynamic {
+        while is_tie(&votes) && bleach < u16::MAX {
+            let raised = votes_at(responses, bleach + 1);
+            if raised.iter().all(|&(v, _)| v == 0) {
+                break;
+            }
+            votes = raised;
+            bleach += 1;
+        }
+    }
+    let mut scores: Vec<Score<L>> = responses
+        .iter()
+        .zip(votes)
+        .map(|((label, _, times_trained, _), (votes, score))| Score {
+            label: (*label).clone(),
+            votes,
+            score,
+            times_trained: *times_trained,
+        })
+        .collect();
+    // best first, the order of tied classes doesn't depend on the hashmap
+    let tie_breaker = election.tie_breaker;
+    scores.sort_by(|a, b| {
+        let by_training = match tie_breaker {
+            TieBreaker::MostTrained => b.times_trained.cmp(&a.times_trained),
+            TieBreaker::LeastTrained => a.times_trained.cmp(&b.times_trained),
+            _ => Ordering::Equal,
+        };
+        // weights are checked to be finite, so scores are never NaN
+        b.score
+            .partial_cmp(&a.score)
+            .unwrap_or(Ordering::Equal)
+            .then(by_training)
+            .then_with(|| a.label.cmp(&b.label))
+    });
+
+    let best = scores.first().ok_or(WisardError::WisardEmptyModel)?.score;
+    let tied = scores.iter().take_while(|s| s.score == best).count();
+    let outcome = match tie_breaker {
+        TieBreaker::Ambiguous if tied > 1 => {
+            Outcome::Ambiguous(scores[..tied].iter().map(|s| s.label.clone()).collect())
+        }
+        TieBreaker::Random(seed) if tied > 1 => {
+            let pick = mix(seed ^ hash_permutation(addresses)) % tied as u64;
+            scores[..tied].rotate_left(pick as usize);
+            Outcome::Label(scores[0].label.clone())
+        }
+        _ => Outcome::Label(scores[0].label.clone()),
+    };
+    let outcome = if election.reject_rule.rejects(&scores) {
+        Outcome::Rejected
+    } else {
+        outcome
+    };
+    let margin = match scores.get(1) {
+        Some(second) => scores[0].score - second.score,
+        None => scores[0].score,
+    };
+
+    Ok(Prediction {
+        label: scores[0].label.clone(),
+        margin,
+        bleach,
+        outcome,
+        scores,
+    })
+}
+
+// how the permutation of a tuple becomes the address of a RAM
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub enum AddressMode {
+    // ids handed out in the order permutations are first seen while training,
+    // kept in rank_tables
+    RankTable,
+    // position of the permutation in lexicographic order, exact for tuples of
+    // up to 20 positions and wrapping (like a hash) beyond that
+    Lehmer,
+    // a fixed hash of the permutation
+    Hash,
+}
+
+impl AddressMode {
+    // the address of a permutation when it doesn't depend on the training data
+    fn stateless(&self, permutation: &[u64]) -> Option<u64> {
+        match self {
+            AddressMode::RankTable => None,
+            AddressMode::Lehmer => Some(lehmer_code(permutation)),
+            AddressMode::Hash => Some(hash_permutation(permutation)),
+        }
+    }
+}
+
+// what classify does with a permutation that never showed up during training,
+// only matters for AddressMode::RankTable
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub enum UnseenPolicy {
+    // every unseen permutation gets the same address, one past the last rank
+    Shared,
+    // unseen permutations don't vote
+    NeverVote,
+    // each unseen permutation gets its own address, away from the learned ones
+    Hash,
+    // use the rank of the closest known permutation by Kendall tau distance,
+    // scans rank_tables so it gets slow as the model grows
+    Nearest,
+}
+
+// address ignored by every discriminator
+pub(crate) const NO_VOTE: u64 = u64::MAX;
+// learned ranks count up from 0 and never reach this bit
+const UNSEEN_HASH: u64 = 1 << 63;
+
+fn hash_permutation(permutation: &[u64]) -> u64 {
+    permutation
+        .iter()
+        .fold(permutation.len() as u64, |h, &p| mix(h ^ p))
+}
+
+// number of pairs of elements in a different order in `a` and in the
+// permutation whose inverse is `position`
+fn kendall_tau_distance(a: &[u64], position: &[usize]) -> usize {
+    let mut distance = 0;
+    for i in 0..a.len() {
+        for j in i + 1..a.len() {
+            let (x, y) = (a[i] as usize, a[j] as usize);
+            if x >= position.len() || y >= position.len() || position[x] > position[y] {
+                distance += 1;
+            }
+        }
+    }
+    distance
+}
+
+fn lehmer_code(permutation: &[u64]) -> u64 {
+    let n = permutation.len();
+    let mut code: u64 = 0;
+    for i in 0..n {
+        let smaller_after = permutation[i + 1..]
+            .iter()
+            .filter(|&&p| p < permutation[i])
+            .count() as u64;
+        code = code
+            .wrapping_mul((n - i) as u64)
+            .wrapping_add(smaller_after);
+    }
+    code
+}
+
+// how values that can't be compared, such as NaN, are ranked
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub enum NanPolicy {
+    // train and classify fail with WisardIncomparable
+    Reject,
+    // NaN sorts before every other value
+    First,
+    // NaN sorts after every other value
+    Last,
+    // floats sort by RankOrder: negative NaNs first, then -0 before +0, then
+    // positive NaNs last. Other types keep partial_cmp
+    TotalOrder,
+}
+
+// how the values of a tuple are sorted with NanPolicy::TotalOrder, every
+// sample type needs it and only floats change the default
+pub trait RankOrder: PartialOrd {
+    fn rank_cmp(&self, other: &Self) -> Ordering {
+        self.partial_cmp(other).unwrap_or(Ordering::Equal)
+    }
+}
+
+// flipping the bits below the sign of negative floats makes the integer order
+// of the bits the IEEE 754 total order
+impl RankOrder for f32 {
+    fn rank_cmp(&self, other: &Self) -> Ordering {
+        let key = |v: f32| {
+            let bits = v.to_bits() as i32;
+            bits ^ (((bits >> 31) as u32) >> 1) as i32
+        };
+        key(*self).cmp(&key(*other))
+    }
+}
+
+impl RankOrder for f64 {
+    fn rank_cmp(&self, other: &Self) -> Ordering {
+        let key = |v: f64| {
+            let bits = v.to_bits() as i64;
+            bits ^ (((bits >> 63) as u64) >> 1) as i64
+        };
+        key(*self).cmp(&key(*other))
+    }
+}
+
+macro_rules! partial_rank_order {
+    ($($t:ty),*) => {
+        $(impl RankOrder for $t {})*
+    };
+}
+
+partial_rank_order!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, char, bool);
+
+// what happens to the inputs left over when the input length isn't a multiple
+// of the tuple size
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub enum RemainderPolicy {
+    // one more tuple, filled up with padding that sorts before every value
+    Pad,
+    // one more tuple, filled up by reading inputs again from the start of the
+    // mapping
+    Wrap,
+    // the leftover inputs are never read
+    Drop,
+    // one more tuple with just the leftover inputs
+    Shorter,
+}
+
+impl RemainderPolicy {
+    // RAMs needed for input_length inputs in tuples of addr_length
+    pub fn number_of_hashtables(&self, input_length: usize, addr_length: usize) -> usize {
+        let whole = input_length / addr_length;
+        match (self, input_length % addr_length) {
+            (_, 0) | (RemainderPolicy::Drop, _) => whole,
+            _ => whole + 1,
+        }
+    }
+
+    // positions of the mapping, the inputs it reads plus padding
+    pub(crate) fn mapping_length(
+        &self,
+        input_length: usize,
+        number_of_hashtables: usize,
+        addr_length: usize,
+    ) -> usize {
+        match self {
+            RemainderPolicy::Shorter => input_length,
+            _ => number_of_hashtables * addr_length,
+        }
+    }
+}
+
+// buffers used while turning a sample into addresses, keeping one around
+// avoids allocating on every call to train or classify
+#[derive(Debug, Default)]
+pub struct Scratch<T> {
+    tuple: Vec<(u64, T)>,
+    // positions of the tuple holding padding
+    padding: Vec<u64>,
+    permutation: Vec<u64>,
+    addresses: Vec<u64>,
+}
+
+impl<T> Scratch<T> {
+    pub fn new() -> Self {
+        Scratch {
+            tuple: Vec::new(),
+            padding: Vec::new(),
+            permutation: Vec::new(),
+            addresses: Vec::new(),
+        }
+    }
+
+    fn flush_tuple(&mut self, nan_policy: &NanPolicy) -> Result<(), WisardError>
+    where
+        T: RankOrder,
+    {
+        // NaN is the only value that doesn't compare with itself. A tuple of one
+        // value is never compared while sorting, so it's checked first
+        let mut incomparable = *nan_policy == NanPolicy::Reject
+            && self.tuple.iter().any(|a| a.1.partial_cmp(&a.1).is_none());
+        // TotalOrder also tells apart values that compare equal, like -0 and 0
+        let total = *nan_policy == NanPolicy::TotalOrder;
+        self.tuple.sort_by(|a, b| match a.1.partial_cmp(&b.1) {
+            Some(order) if !total => order,
+            _ => {
+                let a_nan = a.1.partial_cmp(&a.1).is_none();
+                let b_nan = b.1.partial_cmp(&b.1).is_none();
+                match nan_policy {
+                    NanPolicy::Reject => {
+                        incomparable = true;
+                        Ordering::Equal
+                    }
+                    NanPolicy::First => b_nan.cmp(&a_nan),
+                    NanPolicy::Last => a_nan.cmp(&b_nan),
+                    NanPolicy::TotalOrder => a.1.rank_cmp(&b.1),
+                }
+            }
+        });
+        if incomparable {
+            self.tuple.clear();
+            self.padding.clear();
+            return Err(WisardError::WisardIncomparable);
+        }
+        // padding sorts first, in the order of its positions
+        self.permutation.append(&mut self.padding);
+        self.permutation.extend(self.tuple.iter().map(|a| a.0));
+        self.tuple.clear();
+        Ok(())
+    }
+}
+
+const MODEL_NAME: &str = "dict_wisard";
+const MISSING_RAM: &str = "a discriminator has fewer RAMs than hashtables";
+
+// bounds on a model coming from an untrusted stream, see load_bounded
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadLimits {
+    pub max_bytes: u64,
+    pub max_labels: usize,
+    // entries of the rank tables
+    pub max_rank_entries: usize,
+    // addresses stored across the hashtable RAMs of every discriminator
+    pub max_ram_entries: usize,
+}
+
+impl Default for LoadLimits {
+    fn default() -> Self {
+        LoadLimits {
+            max_bytes: 500_000_000,
+            max_labels: 10_000,
+            max_rank_entries: 10_000_000,
+            max_ram_entries: 50_000_000,
+        }
+    }
+}
+
+impl LoadLimits {
+    // only the structure of the model is checked
+    pub fn unlimited() -> Self {
+        LoadLimits {
+            max_bytes: u64::MAX,
+            max_labels: usize::MAX,
+            max_rank_entries: usize::MAX,
+            max_ram_entries: usize::MAX,
+        }
+    }
+}
+
+// a discriminator as export writes it, with the addresses of each hashtable
+// sorted so two documents can be diffed
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct DiscriminatorDocument {
+    pub number_of_hashtables: u16,
+    pub times_trained: u64,
+    pub rams: RamsDocument,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub enum RamsDocument {
+    // (address, count) pairs of every hashtable
+    HashTable(Vec<Vec<(u64, u16)>>),
+    Bloom(Vec<BloomFilter>),
+    CountingBloom(Vec<CountingBloomFilter>),
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ClassDocument<L> {
+    pub label: L,
+    pub discriminator: DiscriminatorDocument,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct RankDocument {
+    pub permutation: Vec<u64>,
+    pub rank: u64,
+}
+
+// a whole model as export writes it, classes sorted by label and rank tables
+// by rank
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ModelDocument<L> {
+    pub model: String,
+    pub element_type: String,
+    pub label_type: String,
+    pub number_of_hashtables: u16,
+    pub addr_length: u16,
+    pub bleach: u16,
+    pub input_length: usize,
+    pub remainder: RemainderPolicy,
+    pub mapping_strategy: MappingStrategy,
+    pub bleach_policy: BleachPolicy,
+    pub tie_breaker: TieBreaker,
+    pub normalization: Normalization,
+    // sorted by label
+    pub class_weights: Vec<(L, f64)>,
+    pub reject_rule: RejectRule,
+    // sorted by label
+    pub label_thresholds: Vec<(L, f64)>,
+    pub mapping: Vec<u64>,
+    pub last_rank: u64,
+    pub rank_tables: Vec<RankDocument>,
+    pub ram: RamKind,
+    pub seed: Option<u64>,
+    pub address_mode: AddressMode,
+    pub unseen_policy: UnseenPolicy,
+    pub nan_policy: NanPolicy,
+    pub classes: Vec<ClassDocument<L>>,
+}
+
+// layout of the model before the file format was versioned
+#[derive(Deserialize, Serialize)]
+struct LegacyDiscriminator {
+    number_of_hashtables: u16,
+    h_rams: Vec<HashMap<u64, u16>>,
+    times_trained: u64,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(bound(
+    serialize = "L: Serialize + Eq + Hash",
+    deserialize = "L: Deserialize<'de> + Eq + Hash"
+))]
+struct LegacyWisard<L> {
+    discs: HashMap<L, LegacyDiscriminator>,
+    addr_length: u16,
+    number_of_hashtables: u16,
+    mapping: Vec<u64>,
+    last_rank: u64,
+    rank_tables: HashMap<Vec<u64>, u64>,
+    bleach: u16,
+}
+
+// layout of the model in version 1 files, before the input length
+#[derive(Deserialize, Serialize)]
+#[serde(bound(
+    serialize = "L: Serialize + Eq + Hash",
+    deserialize = "L: Deserialize<'de> + Eq + Hash"
+))]
+struct WisardV1<L> {
+    discs: HashMap<L, Discriminator>,
+    addr_length: u16,
+    number_of_hashtables: u16,
+    mapping: Vec<u64>,
+    last_rank: u64,
+    rank_tables: HashMap<Vec<u64>, u64>,
+    bleach: u16,
+    ram: RamKind,
+    seed: Option<u64>,
+    address_mode: AddressMode,
+    unseen_policy: UnseenPolicy,
+    nan_policy: NanPolicy,
+}
+
+// how classify decides, on top of the votes. Saved as a self-describing
+// section of the model file, so a field added here reads as its default from
+// older files and doesn't need a new format version
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(
+    default,
+    bound(
+        serialize = "L: Serialize + Eq + Hash",
+        deserialize = "L: Deserialize<'de> + Eq + Hash"
+    )
+)]
+pub(crate) struct Policies<L> {
+    bleach_policy: BleachPolicy,
+    tie_breaker: TieBreaker,
+    normalization: Normalization,
+    class_weights: HashMap<L, f64>,
+    reject_rule: RejectRule,
+    // what classify_multi compares the score of each label to
+    label_thresholds: HashMap<L, f64>,
+}
+
+impl<L> Default for Policies<L> {
+    fn default() -> Self {
+        Policies {
+            bleach_policy: BleachPolicy::Fixed,
+            tie_breaker: TieBreaker::Label,
+            normalization: Normalization::None,
+            class_weights: HashMap::new(),
+            reject_rule: RejectRule::default(),
+            label_thresholds: HashMap::new(),
+        }
+    }
+}
+
+// the policies as MessagePack bytes inside the bincode of the model, with
+// field names, so missing fields can be defaulted
+mod policies_section {
+    use super::Policies;
+    use serde::de::{self, DeserializeOwned};
+    use serde::{ser, Deserialize, Deserializer, Serialize, Serializer};
+    use std::hash::Hash;
+
+    pub fn serialize<S, L>(policies: &Policies<L>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        L: Serialize + Eq + Hash,
+    {
+        let bytes = rmp_serde::to_vec_named(policies).map_err(ser::Error::custom)?;
+        serializer.serialize_bytes(&bytes)
+    }
+
+    pub fn deserialize<'de, D, L>(deserializer: D) -> Result<Policies<L>, D::Error>
+    where
+        D: Deserializer<'de>,
+        L: DeserializeOwned + Eq + Hash,
+    {
+        let bytes: Vec<u8> = Deserialize::deserialize(deserializer)?;
+        rmp_serde::from_slice(&bytes).map_err(de::Error::custom)
+    }
+}
+
+// labels can be of any type L that is Eq + Hash + Clone, and Serialize to save
+// the model
 #[derive(Deserialize, Serialize, Debug)]
-pub struct Wisard<T> {
-    discs: HashMap<String, Discriminator>,
+#[serde(bound(
+    serialize = "L: Serialize + Eq + Hash",
+    deserialize = "L: DeserializeOwned + Eq + Hash"
+))]
+pub struct Wisard<T, L = String> {
+    discs: HashMap<L, Discriminator>,
     addr_length: u16,
     number_of_hashtables: u16,
+    // values every sample has, the mapping may read fewer or some twice
+    input_length: usize,
     mapping: Vec<u64>,
     last_rank: u64,
     rank_tables: HashMap<Vec<u64>, u64>,
     bleach: u16,
+    ram: RamKind,
+    seed: Option<u64>,
+    address_mode: AddressMode,
+    remainder: RemainderPolicy,
+    // what erase builds the next mapping with
+    mapping_strategy: MappingStrategy,
+    unseen_policy: UnseenPolicy,
+    nan_policy: NanPolicy,
+    #[serde(with = "policies_section")]
+    policies: Policies<L>,
     phantom: PhantomData<T>,
 }
 
-impl<T> Wisard<T> {
-    pub fn get_info(&self) -> (u16, u16, u16) {
-        return (self.number_of_hashtables, self.addr_length, self.bleach);
+impl<T, L> Wisard<T, L> {
+    pub fn get_info(&self) -> (u16, u16, u16) {
+        return (self.number_of_hashtables, self.addr_length, self.bleach);
+    }
+    pub fn new() -> Self
+    where
+        T: RankOrder + Copy + Send + Sync,
+    {
+        Wisard::with_params(28, 28, 0)
+    }
+
+    pub fn get_ram_kind(&self) -> &RamKind {
+        &self.ram
+    }
+
+    pub fn get_seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    pub fn get_address_mode(&self) -> &AddressMode {
+        &self.address_mode
+    }
+
+    pub fn get_input_length(&self) -> usize {
+        self.input_length
+    }
+
+    pub fn get_remainder(&self) -> &RemainderPolicy {
+        &self.remainder
+    }
+
+    pub fn get_mapping_strategy(&self) -> &MappingStrategy {
+        &self.mapping_strategy
+    }
+
+    // the fixed threshold, where dynamic bleaching starts
+    pub fn set_bleach(&mut self, bleach: u16) {
+        self.bleach = bleach;
+    }
+
+    pub fn get_bleach_policy(&self) -> &BleachPolicy {
+        &self.policies.bleach_policy
+    }
+
+    pub fn set_bleach_policy(&mut self, bleach_policy: BleachPolicy) {
+        self.policies.bleach_policy = bleach_policy;
+    }
+
+    pub fn get_tie_breaker(&self) -> &TieBreaker {
+        &self.policies.tie_breaker
+    }
+
+    // only changes classification, so it can be switched on a trained model
+    pub fn set_tie_breaker(&mut self, tie_breaker: TieBreaker) {
+        self.policies.tie_breaker = tie_breaker;
+    }
+
+    pub fn get_normalization(&self) -> &Normalization {
+        &self.policies.normalization
+    }
+
+    pub fn set_normalization(&mut self, normalization: Normalization) {
+        self.policies.normalization = normalization;
+    }
+
+    // the weight the score of label is multiplied by, 1 unless set
+    pub fn get_class_weight(&self, label: &L) -> f64
+    where
+        L: Eq + Hash,
+    {
+        self.policies
+            .class_weights
+            .get(label)
+            .copied()
+            .unwrap_or(1.0)
+    }
+
+    // for cost-sensitive decisions, a class that is costly to miss gets a
+    // weight above 1. The class doesn't have to be trained yet
+    pub fn set_class_weight(&mut self, label: L, weight: f64) -> Result<(), WisardError>
+    where
+        L: Eq + Hash,
+    {
+        if !weight.is_finite() || weight < 0.0 {
+            return Err(WisardError::WisardInvalidConfig(format!(
+                "a class weight must be finite and at least 0, not {}",
+                weight
+            )));
+        }
+        self.policies.class_weights.insert(label, weight);
+        Ok(())
+    }
+
+    // replaces every weight, or none when one of them is invalid
+    pub fn set_class_weights(&mut self, class_weights: HashMap<L, f64>) -> Result<(), WisardError>
+    where
+        L: Eq + Hash,
+    {
+        let mut checked = HashMap::with_capacity(class_weights.len());
+        std::mem::swap(&mut self.policies.class_weights, &mut checked);
+        for (label, weight) in class_weights {
+            if let Err(error) = self.set_class_weight(label, weight) {
+                self.policies.class_weights = checked;
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn clear_class_weights(&mut self) {
+        self.policies.class_weights.clear();
+    }
+
+    pub fn get_reject_rule(&self) -> &RejectRule {
+        &self.policies.reject_rule
+    }
+
+    // only changes classification, so it can be switched on a trained model
+    pub fn set_reject_rule(&mut self, reject_rule: RejectRule) -> Result<(), WisardError> {
+        reject_rule.check()?;
+        self.policies.reject_rule = reject_rule;
+        Ok(())
+    }
+
+    pub fn get_label_threshold(&self, label: &L) -> f64
+    where
+        L: Eq + Hash,
+    {
+        self.policies
+            .label_thresholds
+            .get(label)
+            .copied()
+            .unwrap_or(DEFAULT_LABEL_THRESHOLD)
     }
-    pub fn new() -> Self
+
+    pub fn set_label_threshold(&mut self, label: L, threshold: f64) -> Result<(), WisardError>
     where
-        T: PartialOrd + Copy + Send + Sync,
+        L: Eq + Hash,
     {
-        Wisard::with_params(28, 28, 0)
+        if !threshold.is_finite() {
+            return Err(WisardError::WisardInvalidConfig(format!(
+                "a label threshold must be finite, not {}",
+                threshold
+            )));
+        }
+        self.policies.label_thresholds.insert(label, threshold);
+        Ok(())
+    }
+
+    pub fn get_unseen_policy(&self) -> &UnseenPolicy {
+        &self.unseen_policy
+    }
+
+    // only changes classification, so it can be switched on a trained model
+    pub fn set_unseen_policy(&mut self, unseen_policy: UnseenPolicy) {
+        self.unseen_policy = unseen_policy;
+    }
+
+    pub fn get_nan_policy(&self) -> &NanPolicy {
+        &self.nan_policy
+    }
+
+    pub fn set_nan_policy(&mut self, nan_policy: NanPolicy) {
+        self.nan_policy = nan_policy;
     }
 
     pub fn with_params(number_of_hashtables: u16, addr_length: u16, bleach: u16) -> Self {
-        // randomizes the mapping
-        let mut rng_mapping =
-            (0..addr_length as u64 * number_of_hashtables as u64).collect::<Vec<u64>>();
-        rng_mapping.shuffle(&mut thread_rng());
+        Wisard::with_ram(
+            number_of_hashtables,
+            addr_length,
+            bleach,
+            RamKind::HashTable,
+            None,
+        )
+    }
+
+    pub fn with_seed(number_of_hashtables: u16, addr_length: u16, bleach: u16, seed: u64) -> Self {
+        Wisard::with_ram(
+            number_of_hashtables,
+            addr_length,
+            bleach,
+            RamKind::HashTable,
+            Some(seed),
+        )
+    }
+
+    pub fn with_ram(
+        number_of_hashtables: u16,
+        addr_length: u16,
+        bleach: u16,
+        ram: RamKind,
+        seed: Option<u64>,
+    ) -> Self {
+        let rng_mapping =
+            mapping::shuffled(addr_length as usize * number_of_hashtables as usize, seed);
 
-        Wisard::<T> {
+        Wisard::<T, L> {
             discs: HashMap::new(),
             addr_length: addr_length,
             number_of_hashtables: number_of_hashtables,
+            input_length: rng_mapping.len(),
             mapping: rng_mapping,
             last_rank: 0,
             rank_tables: HashMap::new(),
             bleach: bleach,
+            ram,
+            seed,
+            address_mode: AddressMode::RankTable,
+            // the mapping fits the input, so there's nothing left over
+            remainder: RemainderPolicy::Drop,
+            mapping_strategy: MappingStrategy::Random,
+            unseen_policy: UnseenPolicy::Shared,
+            nan_policy: NanPolicy::Reject,
+            policies: Policies::default(),
             phantom: PhantomData,
         }
     }
 
+    // the model a WisardConfig describes, once the config is checked
+    pub fn with_config(config: &WisardConfig) -> Result<Self, WisardError> {
+        config.validate()?;
+        let number_of_hashtables = config.get_number_of_hashtables()?;
+        let addr_length = config.get_addr_length();
+        let mut wisard = Wisard::with_ram(
+            number_of_hashtables,
+            addr_length,
+            config.get_bleach(),
+            config.get_ram().clone(),
+            config.get_seed(),
+        );
+        if let Some(input_length) = config.get_input_length() {
+            wisard.input_length = input_length;
+        }
+        if let Some(remainder) = config.get_remainder() {
+            wisard.remainder = remainder.clone();
+        }
+        wisard.mapping_strategy = config.get_mapping().clone();
+        wisard.policies.bleach_policy = config.get_bleach_policy().clone();
+        wisard.policies.tie_breaker = config.get_tie_breaker().clone();
+        wisard.policies.normalization = config.get_normalization().clone();
+        wisard.policies.reject_rule = config.get_reject_rule().clone();
+        // with_address_mode erases, which builds the mapping of the strategy
+        Ok(wisard.with_address_mode(config.get_address_mode().clone()))
+    }
+
+    // meant to be chained on a constructor, before any training
+    pub fn with_address_mode(mut self, address_mode: AddressMode) -> Self {
+        self.erase();
+        self.address_mode = address_mode;
+        self
+    }
+
     pub fn erase_and_change_hyperparameters(
         &mut self,
         number_of_hashtables: u16,
         addr_length: u16,
         bleach: u16,
+        seed: Option<u64>,
     ) {
+        self.seed = seed;
         self.erase();
-        // randomizes the mapping
-        let mut rng_mapping =
-            (0..addr_length as u64 * number_of_hashtables as u64).collect::<Vec<u64>>();
-        rng_mapping.shuffle(&mut thread_rng());
+        let rng_mapping =
+            mapping::shuffled(addr_length as usize * number_of_hashtables as usize, seed);
 
         self.discs = HashMap::new();
         self.addr_length = addr_length;
         self.number_of_hashtables = number_of_hashtables;
+        self.input_length = rng_mapping.len();
+        self.remainder = RemainderPolicy::Drop;
+        self.mapping_strategy = MappingStrategy::Random;
         self.mapping = rng_mapping;
         self.last_rank = 0;
         self.rank_tables = HashMap::new();
         self.bleach = bleach;
     }
 
-    fn ranks_t(&mut self, samples: Vec<&T>) -> Vec<u64>
+    // writes into scratch.permutation, tuple after tuple, the order of the
+    // positions of each tuple once sorted by value, which is what gets ranked
+    // into an address. A sample of None is padding
+    fn permute<'a, I>(
+        addr_length: usize,
+        nan_policy: &NanPolicy,
+        samples: I,
+        scratch: &mut Scratch<T>,
+    ) -> Result<(), WisardError>
     where
-        T: PartialOrd + Copy + Send + Sync,
+        I: Iterator<Item = Result<Option<&'a T>, WisardError>>,
+        T: RankOrder + Copy + 'a,
     {
-        let mut vetor = Vec::with_capacity(self.addr_length as usize);
-        let mut addresses = Vec::new();
-        for i in (0..samples.len()).step_by(self.addr_length as usize) {
-            if i + self.addr_length as usize <= samples.len() {
-                vetor.append(&mut samples[i..i + self.addr_length as usize].to_vec());
-            } else {
-                vetor.append(&mut samples[i..samples.len()].to_vec());
+        scratch.permutation.clear();
+        scratch.tuple.clear();
+        scratch.padding.clear();
+        for sample in samples {
+            let position = (scratch.tuple.len() + scratch.padding.len()) as u64;
+            match sample? {
+                Some(sample) => scratch.tuple.push((position, *sample)),
+                None => scratch.padding.push(position),
             }
-            let mut tuples: Vec<(u64, &T)> = vetor
-                .iter()
-                .enumerate()
-                .map(|x| (x.0 as u64, *x.1))
-                .collect();
-            tuples.sort_by(|a, b| a.1.partial_cmp(b.1).unwrap()); // TODO: treat the Option
-            let address: Vec<u64> = tuples.iter().map(|a| a.0).collect();
-            if !self.rank_tables.contains_key(&address) {
-                self.rank_tables.insert(address.clone(), self.last_rank);
-                self.last_rank += 1;
+            if position as usize + 1 == addr_length {
+                scratch.flush_tuple(nan_policy)?;
             }
-            addresses.push(*self.rank_tables.get(&address).unwrap()); // TODO: treat the Option
-            vetor.clear();
         }
+        // the last tuple is shorter with RemainderPolicy::Shorter
+        if !scratch.tuple.is_empty() || !scratch.padding.is_empty() {
+            scratch.flush_tuple(nan_policy)?;
+        }
+        Ok(())
+    }
+
+    fn permute_data(&self, data: &[T], scratch: &mut Scratch<T>) -> Result<(), WisardError>
+    where
+        T: RankOrder + Copy,
+    {
+        if data.len() < self.input_length {
+            return Err(WisardError::WisardInputTooShort {
+                expected: self.input_length,
+                actual: data.len(),
+            });
+        }
+        if data.len() > self.input_length {
+            return Err(WisardError::WisardLengthMismatch {
+                expected: self.input_length,
+                actual: data.len(),
+            });
+        }
+        let pad = self.remainder == RemainderPolicy::Pad;
+        let samples = self.mapping.iter().map(|&i| match data.get(i as usize) {
+            Some(sample) => Ok(Some(sample)),
+            None if pad => Ok(None),
+            None => Err(WisardError::WisardInvalidModel(
+                "the mapping reads past the input",
+            )),
+        });
+        Self::permute(
+            self.addr_length as usize,
+            &self.nan_policy,
+            samples,
+            scratch,
+        )
+    }
+
+    fn rank_t(&mut self, address: &[u64]) -> u64 {
+        if let Some(rank) = self.rank_tables.get(address) {
+            return *rank;
+        }
+        let rank = self.last_rank;
+        self.rank_tables.insert(address.to_vec(), rank);
+        self.last_rank += 1;
+        rank
+    }
+
+    fn rank_c(&self, address: &[u64]) -> u64 {
+        match self.rank_tables.get(address) {
+            Some(rank) => *rank,
+            None => match self.unseen_policy {
+                UnseenPolicy::Shared => 1 + self.last_rank,
+                UnseenPolicy::NeverVote => NO_VOTE,
+                UnseenPolicy::Hash => UNSEEN_HASH | hash_permutation(address),
+                UnseenPolicy::Nearest => self.nearest_rank(address).unwrap_or(NO_VOTE),
+            },
+        }
+    }
+
+    // rank of the known permutation with the fewest pairs in a different order
+    fn nearest_rank(&self, address: &[u64]) -> Option<u64> {
+        let mut position = vec![0; address.len()];
+        for (i, &p) in address.iter().enumerate() {
+            position[p as usize] = i;
+        }
+        self.rank_tables
+            .iter()
+            .filter(|(known, _)| known.len() == address.len())
+            .min_by_key(|(known, rank)| (kendall_tau_distance(known, &position), **rank))
+            .map(|(_, rank)| *rank)
+    }
+
+    // turns the permutation held by scratch into addresses, learning new ranks
+    fn ranks_t_into(&mut self, permutation: &[u64], addresses: &mut Vec<u64>) {
+        addresses.clear();
+        for address in permutation.chunks(self.addr_length as usize) {
+            let rank = match self.address_mode.stateless(address) {
+                Some(rank) => rank,
+                None => self.rank_t(address),
+            };
+            addresses.push(rank);
+        }
+    }
+
+    fn ranks_c_into(&self, permutation: &[u64], addresses: &mut Vec<u64>) {
+        addresses.clear();
+        for address in permutation.chunks(self.addr_length as usize) {
+            let rank = match self.address_mode.stateless(address) {
+                Some(rank) => rank,
+                None => self.rank_c(address),
+            };
+            addresses.push(rank);
+        }
+    }
+
+    #[cfg(test)]
+    fn ranks_t(&mut self, samples: &[T]) -> Vec<u64>
+    where
+        T: RankOrder + Copy,
+    {
+        let mut scratch = Scratch::new();
+        let mut addresses = Vec::new();
+        Self::permute(
+            self.addr_length as usize,
+            &self.nan_policy,
+            samples.iter().map(|sample| Ok(Some(sample))),
+            &mut scratch,
+        )
+        .unwrap();
+        self.ranks_t_into(&scratch.permutation, &mut addresses);
         addresses
     }
 
-    fn ranks_c(&self, samples: Vec<&T>) -> Vec<u64>
+    pub fn train(&mut self, data: &[T], label: L) -> Result<(), WisardError>
+    where
+        T: RankOrder + Copy + Send + Sync,
+        L: Eq + Hash + Clone,
+    {
+        self.train_with_scratch(data, label, &mut Scratch::new())
+    }
+
+    // same as train, reusing the buffers of scratch between calls
+    pub fn train_with_scratch(
+        &mut self,
+        data: &[T],
+        label: L,
+        scratch: &mut Scratch<T>,
+    ) -> Result<(), WisardError>
+    where
+        T: RankOrder + Copy + Send + Sync,
+        L: Eq + Hash + Clone,
+    {
+        self.permute_data(data, scratch)?;
+        let mut addresses = std::mem::take(&mut scratch.addresses);
+        self.ranks_t_into(&scratch.permutation, &mut addresses);
+        let number_of_hashtables = self.number_of_hashtables;
+        let ram = &self.ram;
+        let disc = self
+            .discs
+            .entry(label)
+            .or_insert_with(|| Discriminator::with_ram(number_of_hashtables, ram));
+        let result = disc.train(&addresses);
+        scratch.addresses = addresses;
+        result
+    }
+
+    // trains every label of the set on the sample, for samples with several
+    // tags. classify_multi gives the tags back
+    pub fn train_multi(&mut self, data: &[T], labels: &[L]) -> Result<(), WisardError>
     where
-        T: PartialOrd + Copy + Send + Sync,
+        T: RankOrder + Copy + Send + Sync,
+        L: Eq + Hash + Clone,
     {
-        let mut vetor = Vec::with_capacity(self.addr_length as usize);
+        // without a tag the sample would only grow the rank tables
+        if labels.is_empty() {
+            return Err(WisardError::WisardInvalidConfig(
+                "a sample needs at least one tag to be trained".to_string(),
+            ));
+        }
+        let mut scratch = Scratch::new();
+        self.permute_data(data, &mut scratch)?;
         let mut addresses = Vec::new();
-        for i in (0..samples.len()).step_by(self.addr_length as usize) {
-            if i + self.addr_length as usize <= samples.len() {
-                vetor.append(&mut samples[i..i + self.addr_length as usize].to_vec());
-            } else {
-                vetor.append(&mut samples[i..samples.len()].to_vec());
+        self.ranks_t_into(&scratch.permutation, &mut addresses);
+        let number_of_hashtables = self.number_of_hashtables;
+        let ram = &self.ram;
+        for (i, label) in labels.iter().enumerate() {
+            // a tag given twice is trained once
+            if labels[..i].contains(label) {
+                continue;
             }
-            let mut tuples: Vec<(u64, &T)> = vetor
-                .iter()
-                .enumerate()
-                .map(|x| (x.0 as u64, *x.1))
-                .collect();
-            tuples.sort_by(|a, b| a.1.partial_cmp(b.1).unwrap()); // TODO: treat the Option
-            let address: Vec<u64> = tuples.iter().map(|a| a.0).collect();
-            if !self.rank_tables.contains_key(&address) {
-                // self.rank_tables.insert(address.clone(), self.last_rank);
-                let tmp_rank = 1 + self.last_rank;
-                addresses.push(tmp_rank);
+            self.discs
+                .entry(label.clone())
+                .or_insert_with(|| Discriminator::with_ram(number_of_hashtables, ram))
+                .train(&addresses)?;
+        }
+        Ok(())
+    }
+
+    // same model as calling train on every sample in order, but the sorting of
+    // tuples and the training of each discriminator are spread across threads
+    pub fn train_batch(&mut self, data: &[Vec<T>], labels: &[L]) -> Result<(), WisardError>
+    where
+        T: RankOrder + Copy + Send + Sync,
+        L: Eq + Hash + Clone + Send + Sync,
+    {
+        if data.len() != labels.len() {
+            return Err(WisardError::WisardLengthMismatch {
+                expected: data.len(),
+                actual: labels.len(),
+            });
+        }
+        // stateless addresses can be computed right away, learned ranks need
+        // the permutations to go through rank_tables first
+        let stateless = self.address_mode != AddressMode::RankTable;
+        let permutations = data
+            .par_iter()
+            .map_init(Scratch::new, |scratch, d| {
+                self.permute_data(d, scratch)?;
+                if stateless {
+                    self.ranks_c_into(&scratch.permutation, &mut scratch.addresses);
+                    Ok(scratch.addresses.clone())
+                } else {
+                    Ok(scratch.permutation.clone())
+                }
+            })
+            .collect::<Result<Vec<_>, WisardError>>()?;
+
+        // ranks are handed out in sample order so they match sequential training
+        let mut batches: HashMap<&L, Vec<Vec<u64>>> = HashMap::new();
+        for (permutation, label) in permutations.into_iter().zip(labels) {
+            let addresses = if stateless {
+                permutation
             } else {
-                addresses.push(*self.rank_tables.get(&address).unwrap()); // TODO: treat the Option
+                let mut addresses = Vec::with_capacity(self.number_of_hashtables as usize);
+                self.ranks_t_into(&permutation, &mut addresses);
+                addresses
+            };
+            batches.entry(label).or_default().push(addresses);
+        }
+
+        for label in batches.keys() {
+            if !self.discs.contains_key(*label) {
+                self.discs.insert(
+                    (*label).clone(),
+                    Discriminator::with_ram(self.number_of_hashtables, &self.ram),
+                );
             }
-            vetor.clear();
         }
-        addresses
+        let work: Vec<(&mut Discriminator, Vec<Vec<u64>>)> = self
+            .discs
+            .iter_mut()
+            .filter_map(|(label, disc)| batches.remove(label).map(|batch| (disc, batch)))
+            .collect();
+        work.into_par_iter().try_for_each(|(disc, batch)| {
+            batch.iter().try_for_each(|addresses| disc.train(addresses))
+        })
     }
 
-    pub fn train(&mut self, data: Vec<T>, label: String) -> Result<(), WisardError>
+    // forgets a sample trained with label, a discriminator that ends up with
+    // no training at all is dropped
+    pub fn untrain(&mut self, data: &[T], label: &L) -> Result<(), WisardError>
     where
-        T: PartialOrd + Copy + Send + Sync,
+        T: RankOrder + Copy + Send + Sync,
+        L: Eq + Hash + Clone,
     {
-        if !self.discs.contains_key(&label) {
-            self.discs
-                .insert(label.clone(), Discriminator::new(self.number_of_hashtables));
+        if !self.discs.contains_key(label) {
+            return Err(WisardError::WisardUnknownLabel);
         }
+        let mut scratch = Scratch::new();
+        self.permute_data(data, &mut scratch)?;
+        // only ranks learned in training can point at something to forget, the
+        // rank tables keep them since other samples may share them
+        let mut addresses = Vec::with_capacity(self.number_of_hashtables as usize);
+        for address in scratch.permutation.chunks(self.addr_length as usize) {
+            let rank = match self.address_mode.stateless(address) {
+                Some(rank) => rank,
+                None => *self
+                    .rank_tables
+                    .get(address)
+                    .ok_or(WisardError::WisardNotTrained)?,
+            };
+            addresses.push(rank);
+        }
+        let disc = self
+            .discs
+            .get_mut(label)
+            .ok_or(WisardError::WisardUnknownLabel)?;
+        disc.untrain(&addresses)?;
+        if disc.get_times_trained() == 0 {
+            self.discs.remove(label);
+        }
+        Ok(())
+    }
+
+    pub fn remove_class(&mut self, label: &L) -> Result<(), WisardError>
+    where
+        L: Eq + Hash,
+    {
+        self.discs
+            .remove(label)
+            .map(|_| ())
+            .ok_or(WisardError::WisardUnknownLabel)
+    }
 
-        let samples = self.mapping.clone();
-        let samples = samples
+    // adds the training of other, which must share every hyperparameter and
+    // the mapping, the result is the same as training self on the data of
+    // other after its own
+    pub fn merge(&mut self, other: &Wisard<T, L>) -> Result<(), WisardError>
+    where
+        L: Eq + Hash + Clone,
+    {
+        if self.get_info() != other.get_info()
+            || self.input_length != other.input_length
+            || self.remainder != other.remainder
+            || self.mapping != other.mapping
+            || self.ram != other.ram
+            || self.address_mode != other.address_mode
+        {
+            return Err(WisardError::WisardIncompatible);
+        }
+        // ranks of other are renamed in the order other learned them, giving
+        // the ids that training on its data would have given
+        let mut learned: Vec<(&Vec<u64>, u64)> = other
+            .rank_tables
             .iter()
-            .map(|&i| data.get(i as usize))
-            .collect::<Option<Vec<_>>>()
-            .ok_or_else(|| WisardError::WisardOutOfBounds)?;
-        let addresses: Vec<u64> = self.ranks_t(samples);
-        let disc = self.discs.get_mut(&label).unwrap();
-        disc.train(addresses)?;
+            .map(|(address, rank)| (address, *rank))
+            .collect();
+        learned.sort_by_key(|(_, rank)| *rank);
+        let mut translation = HashMap::new();
+        let mut new_ranks = Vec::new();
+        let mut last_rank = self.last_rank;
+        for (address, rank) in learned {
+            let renamed = match self.rank_tables.get(address) {
+                Some(renamed) => *renamed,
+                None => {
+                    new_ranks.push((address.clone(), last_rank));
+                    last_rank += 1;
+                    last_rank - 1
+                }
+            };
+            if renamed != rank {
+                translation.insert(rank, renamed);
+            }
+        }
+        let translation = if translation.is_empty() {
+            None
+        } else {
+            Some(&translation)
+        };
+
+        // bloom filters fail before anything is touched
+        if translation.is_some() && self.ram != RamKind::HashTable && !other.discs.is_empty() {
+            return Err(WisardError::WisardUnsupported);
+        }
+        self.rank_tables.extend(new_ranks);
+        self.last_rank = last_rank;
+        for (label, other_disc) in &other.discs {
+            let number_of_hashtables = self.number_of_hashtables;
+            let ram = &self.ram;
+            self.discs
+                .entry(label.clone())
+                .or_insert_with(|| Discriminator::with_ram(number_of_hashtables, ram))
+                .merge(other_disc, translation)?;
+        }
         Ok(())
     }
 
-    pub fn classify(&self, data: Vec<T>) -> Result<String, WisardError>
+    // the elected label, WisardAmbiguous when the tie breaker leaves a tie
+    pub fn classify(&self, data: &[T]) -> Result<L, WisardError>
     where
-        T: PartialOrd + Copy + Send + Sync,
+        T: RankOrder + Copy + Send + Sync,
+        L: Ord + Hash + Clone,
     {
-        let samples = self.mapping.clone();
-        let samples = samples
-            .iter()
-            .map(|&i| data.get(i as usize))
-            .collect::<Option<Vec<_>>>()
-            .ok_or_else(|| WisardError::WisardOutOfBounds)?;
-        let addresses: Vec<u64> = self.ranks_c(samples);
-        let discs = &self.discs;
-        let mut votes: Vec<(String, (u64, u64))> = discs
-            .iter()
-            .map(|d| (d.0.to_string(), d.1.classify(&addresses, self.bleach)))
+        self.classify_with_scores(data)?.into_label()
+    }
+
+    pub fn classify_with_scores(&self, data: &[T]) -> Result<Prediction<L>, WisardError>
+    where
+        T: RankOrder + Copy + Send + Sync,
+        L: Ord + Hash + Clone,
+    {
+        self.classify_with_scratch(data, &mut Scratch::new())
+    }
+
+    // every label whose score is above its threshold, best first. There may
+    // be none
+    pub fn classify_multi(&self, data: &[T]) -> Result<Vec<L>, WisardError>
+    where
+        T: RankOrder + Copy + Send + Sync,
+        L: Ord + Hash + Clone,
+    {
+        let scores = self.classify_with_scores(data)?.scores;
+        Ok(scores
+            .into_iter()
+            .filter(|s| s.score > self.get_label_threshold(&s.label))
+            .map(|s| s.label)
+            .collect())
+    }
+
+    // sets the threshold of every trained label tagged on some held-out sample
+    // to the one with the best F1 on those samples, and returns them. Labels
+    // no sample is tagged with keep their threshold, tags the model doesn't
+    // know are ignored
+    pub fn calibrate_thresholds(
+        &mut self,
+        data: &[Vec<T>],
+        labels: &[Vec<L>],
+    ) -> Result<HashMap<L, f64>, WisardError>
+    where
+        T: RankOrder + Copy + Send + Sync,
+        L: Ord + Hash + Clone + Send + Sync,
+    {
+        if data.len() != labels.len() {
+            return Err(WisardError::WisardLengthMismatch {
+                expected: data.len(),
+                actual: labels.len(),
+            });
+        }
+        if self.discs.is_empty() {
+            return Err(WisardError::WisardEmptyModel);
+        }
+        let scores = data
+            .par_iter()
+            .map_init(Scratch::new, |scratch, d| {
+                Ok(self.classify_with_scratch(d, scratch)?.scores)
+            })
+            .collect::<Result<Vec<_>, WisardError>>()?;
+
+        // with no positive example every threshold looks as good
+        let tagged: HashSet<&L> = labels.iter().flatten().collect();
+        let mut pairs: HashMap<L, Vec<(f64, bool)>> = HashMap::new();
+        for (sample, tags) in scores.into_iter().zip(labels) {
+            for score in sample.into_iter().filter(|s| tagged.contains(&s.label)) {
+                let tagged = tags.contains(&score.label);
+                pairs
+                    .entry(score.label)
+                    .or_default()
+                    .push((score.score, tagged));
+            }
+        }
+        let thresholds: HashMap<L, f64> = pairs
+            .into_iter()
+            .map(|(label, mut pairs)| (label, best_threshold(&mut pairs)))
             .collect();
-        votes.sort_by(|a, b| (a.1).0.partial_cmp(&(b.1).0).unwrap());
+        for (label, threshold) in &thresholds {
+            self.policies
+                .label_thresholds
+                .insert(label.clone(), *threshold);
+        }
+        Ok(thresholds)
+    }
 
-        let biggest = votes
-            .iter()
-            .last()
-            .ok_or_else(|| WisardError::WisardOutOfBounds)?;
+    // the k best classes, best first, in the order classify ranks them. Meant
+    // for candidate lists, so neither a tie nor the reject rule keeps the
+    // scores back
+    pub fn classify_top_k(&self, data: &[T], k: usize) -> Result<Vec<Score<L>>, WisardError>
+    where
+        T: RankOrder + Copy + Send + Sync,
+        L: Ord + Hash + Clone,
+    {
+        let mut scores = self.classify_with_scores(data)?.scores;
+        scores.truncate(k);
+        Ok(scores)
+    }
 
-        Ok(
-            biggest.0.clone(), // elected label
+    // same as classify_with_scores, reusing the buffers of scratch between calls
+    pub fn classify_with_scratch(
+        &self,
+        data: &[T],
+        scratch: &mut Scratch<T>,
+    ) -> Result<Prediction<L>, WisardError>
+    where
+        T: RankOrder + Copy + Send + Sync,
+        L: Ord + Hash + Clone,
+    {
+        self.permute_data(data, scratch)?;
+        self.ranks_c_into(&scratch.permutation, &mut scratch.addresses);
+        let election = Election {
+            bleach: self.bleach,
+            bleach_policy: &self.policies.bleach_policy,
+            tie_breaker: &self.policies.tie_breaker,
+            normalization: &self.policies.normalization,
+            class_weights: &self.policies.class_weights,
+            reject_rule: &self.policies.reject_rule,
+        };
+        elect(
+            &self.discs,
+            &scratch.addresses,
+            &election,
+            self.number_of_hashtables,
         )
     }
-    pub fn save(&self) -> Result<Vec<u8>, WisardError> {
-        let encoded: Vec<u8> = match bincode::serialize(&self) {
-            Ok(enc) => enc,
-            Err(_) => return Err(WisardError::WisardValidationFailed),
+
+    // the prediction of every sample, in order. A tie or a rejection is the
+    // outcome of its own sample, only a sample that can't be classified fails
+    // the batch
+    pub fn classify_batch(&self, data: &[Vec<T>]) -> Result<Vec<Prediction<L>>, WisardError>
+    where
+        T: RankOrder + Copy + Send + Sync,
+        L: Ord + Hash + Clone + Send + Sync,
+    {
+        data.par_iter()
+            .map_init(Scratch::new, |scratch, d| {
+                self.classify_with_scratch(d, scratch)
+            })
+            .collect()
+    }
+
+    // sets the bleach to the threshold that gets the most samples right and
+    // returns it, the lowest one when several do as well. A sample is right
+    // when classify would give it its label at that bleach, so the policies of
+    // the model (normalization, class weights, tie breaker...) are applied
+    pub fn tune_bleach(&mut self, data: &[Vec<T>], labels: &[L]) -> Result<u16, WisardError>
+    where
+        T: RankOrder + Copy + Send + Sync,
+        L: Ord + Hash + Clone + Send + Sync,
+    {
+        if data.len() != labels.len() {
+            return Err(WisardError::WisardLengthMismatch {
+                expected: data.len(),
+                actual: labels.len(),
+            });
+        }
+        if self.discs.is_empty() {
+            return Err(WisardError::WisardEmptyModel);
+        }
+        // each sample is right on some ranges of thresholds
+        let ranges = data
+            .par_iter()
+            .zip(labels)
+            .map_init(Scratch::new, |scratch, (d, label)| {
+                self.permute_data(d, scratch)?;
+                self.ranks_c_into(&scratch.permutation, &mut scratch.addresses);
+                self.right_bleaches(&scratch.addresses, label)
+            })
+            .collect::<Result<Vec<_>, WisardError>>()?;
+
+        let mut right = vec![0i64; u16::MAX as usize + 2];
+        for (start, end) in ranges.into_iter().flatten() {
+            right[start as usize] += 1;
+            right[end as usize] -= 1;
+        }
+        let mut best = (0, self.bleach);
+        let mut total = 0;
+        for (bleach, change) in right.iter().enumerate().take(u16::MAX as usize + 1) {
+            total += change;
+            if total > best.0 {
+                best = (total, bleach as u16);
+            }
+        }
+        self.bleach = best.1;
+        Ok(self.bleach)
+    }
+
+    // the ranges [start, end) of thresholds at which addresses are classified
+    // as label. Votes only change at the counts the RAMs hold, so those are the
+    // only thresholds to try
+    fn right_bleaches(&self, addresses: &[u64], label: &L) -> Result<Vec<(u32, u32)>, WisardError>
+    where
+        L: Ord + Hash + Clone,
+    {
+        let mut election = Election {
+            bleach: 0,
+            bleach_policy: &self.policies.bleach_policy,
+            tie_breaker: &self.policies.tie_breaker,
+            normalization: &self.policies.normalization,
+            class_weights: &self.policies.class_weights,
+            reject_rule: &self.policies.reject_rule,
         };
-        Ok(encoded)
+        let responses = respond(&self.discs, addresses, &election, self.number_of_hashtables);
+        let mut steps: Vec<u16> = responses
+            .iter()
+            .flat_map(|(_, c, _, _)| c.iter().copied())
+            .collect();
+        steps.push(0);
+        steps.sort_unstable();
+        steps.dedup();
+
+        let mut ranges = Vec::new();
+        for (i, &bleach) in steps.iter().enumerate() {
+            election.bleach = bleach;
+            if decide(&responses, addresses, &election)?.outcome == Outcome::Label(label.clone()) {
+                let end = steps.get(i + 1).map_or(u16::MAX as u32 + 1, |&s| s as u32);
+                ranges.push((bleach as u32, end));
+            }
+        }
+        Ok(ranges)
     }
-    pub fn load(&mut self, stream: &[u8]) -> Result<(), WisardError> {
-        let decoded: Wisard<T> = match bincode::deserialize(stream) {
-            Ok(res) => res,
-            Err(_) => return Err(WisardError::WisardValidationFailed),
+
+    // the model behind a header, see format for the layout
+    pub fn save(&self) -> Result<Vec<u8>, WisardError>
+    where
+        T: TypeTag,
+        L: Serialize + Eq + Hash + TypeTag,
+    {
+        let body: Vec<u8> = match bincode::serialize(&self) {
+            Ok(enc) => enc,
+            Err(error) => return Err(WisardError::WisardSerialization(error)),
         };
+        let header = Header::new::<T, L>(MODEL_NAME, self.get_info(), &body);
+        format::encode(&header, &body)
+    }
+    pub fn load(&mut self, stream: &[u8]) -> Result<(), WisardError>
+    where
+        T: TypeTag,
+        L: DeserializeOwned + Eq + Hash + TypeTag,
+    {
+        let decoded = Self::decode(stream, u64::MAX)?;
+        self.replace_with(decoded);
+        Ok(())
+    }
+    // load for streams that can't be trusted, nothing is allocated past the
+    // limits and the model is checked before it replaces this one
+    pub fn load_bounded(&mut self, stream: &[u8], limits: &LoadLimits) -> Result<(), WisardError>
+    where
+        T: TypeTag,
+        L: DeserializeOwned + Eq + Hash + TypeTag,
+    {
+        let decoded = Self::decode(stream, limits.max_bytes)?;
+        decoded.validate(limits)?;
+        self.replace_with(decoded);
+        Ok(())
+    }
+    fn replace_with(&mut self, decoded: Wisard<T, L>) {
         self.discs = decoded.discs;
         self.addr_length = decoded.addr_length;
         self.number_of_hashtables = decoded.number_of_hashtables;
+        self.input_length = decoded.input_length;
+        self.remainder = decoded.remainder;
+        self.mapping_strategy = decoded.mapping_strategy;
+        self.policies = decoded.policies;
         self.mapping = decoded.mapping;
         self.last_rank = decoded.last_rank;
         self.rank_tables = decoded.rank_tables;
         self.bleach = decoded.bleach;
+        self.ram = decoded.ram;
+        self.seed = decoded.seed;
+        self.address_mode = decoded.address_mode;
+        self.unseen_policy = decoded.unseen_policy;
+        self.nan_policy = decoded.nan_policy;
+    }
+    // everything classify and train index with has to be in range, so a model
+    // that passes can't make them panic
+    fn validate(&self, limits: &LoadLimits) -> Result<(), WisardError> {
+        let n = self.number_of_hashtables as usize;
+        let a = self.addr_length as usize;
+        if n == 0 || a == 0 {
+            return Err(WisardError::WisardInvalidModel("a size is zero"));
+        }
+        mapping::check_mapping(&self.mapping, self.input_length, n, a, &self.remainder)?;
+        self.mapping_strategy
+            .check(self.input_length, n, a)
+            .map_err(WisardError::WisardInvalidModel)?;
+        if let MappingStrategy::Custom(custom) = &self.mapping_strategy {
+            if *custom != self.mapping {
+                return Err(WisardError::WisardInvalidModel(
+                    "the custom mapping is not the mapping",
+                ));
+            }
+        }
+        // only the last tuple can be shorter
+        let last_tuple = self.mapping.len() - (n - 1) * a;
+        if self.discs.len() > limits.max_labels {
+            return Err(WisardError::WisardLimitExceeded("labels"));
+        }
+        if self.policies.class_weights.len() > limits.max_labels {
+            return Err(WisardError::WisardLimitExceeded("labels"));
+        }
+        if self
+            .policies
+            .class_weights
+            .values()
+            .any(|w| !w.is_finite() || *w < 0.0)
+        {
+            return Err(WisardError::WisardInvalidModel(
+                "a class weight is negative or not finite",
+            ));
+        }
+        if self.policies.label_thresholds.len() > limits.max_labels {
+            return Err(WisardError::WisardLimitExceeded("labels"));
+        }
+        if self
+            .policies
+            .label_thresholds
+            .values()
+            .any(|t| !t.is_finite())
+        {
+            return Err(WisardError::WisardInvalidModel(
+                "a label threshold is not finite",
+            ));
+        }
+        if self.policies.reject_rule.check().is_err() {
+            return Err(WisardError::WisardInvalidModel(
+                "the reject rule has a margin out of [0, 1]",
+            ));
+        }
+        if self.rank_tables.len() > limits.max_rank_entries {
+            return Err(WisardError::WisardLimitExceeded("rank entries"));
+        }
+        for (address, rank) in &self.rank_tables {
+            if *rank >= self.last_rank
+                || !(mapping::is_permutation(address, a)
+                    || mapping::is_permutation(address, last_tuple))
+            {
+                return Err(WisardError::WisardInvalidModel(
+                    "the rank tables hold an invalid entry",
+                ));
+            }
+        }
+        let mut ram_entries: usize = 0;
+        for disc in self.discs.values() {
+            if !disc.has_shape(self.number_of_hashtables, &self.ram) {
+                return Err(WisardError::WisardInvalidModel(
+                    "a discriminator doesn't match the hyperparameters",
+                ));
+            }
+            ram_entries = ram_entries.saturating_add(disc.ram_entries());
+        }
+        if ram_entries > limits.max_ram_entries {
+            return Err(WisardError::WisardLimitExceeded("RAM entries"));
+        }
+        Ok(())
+    }
+    fn decode(stream: &[u8], max_bytes: u64) -> Result<Wisard<T, L>, WisardError>
+    where
+        T: TypeTag,
+        L: DeserializeOwned + Eq + Hash + TypeTag,
+    {
+        let version = match format::read_version(stream)? {
+            Some(version) => version,
+            None => return Self::migrate_v0(stream, max_bytes),
+        };
+        let (header, body) = format::read_header_with_limit(stream, max_bytes)?;
+        header.expect::<T, L>(MODEL_NAME)?;
+        if version == 1 {
+            return Self::migrate_v1(body, max_bytes);
+        }
+        match format::options(max_bytes).deserialize(body) {
+            Ok(decoded) => Ok(decoded),
+            Err(error) => Err(WisardError::WisardMalformed(error)),
+        }
+    }
+    // version 1 had no input length, remainder or mapping strategy, the mapping
+    // read every input once, and no policies
+    fn migrate_v1(body: &[u8], max_bytes: u64) -> Result<Wisard<T, L>, WisardError>
+    where
+        L: DeserializeOwned + Eq + Hash,
+    {
+        let v1: WisardV1<L> = match format::options(max_bytes).deserialize(body) {
+            Ok(v1) => v1,
+            Err(error) => return Err(WisardError::WisardMalformed(error)),
+        };
+        Ok(Wisard {
+            discs: v1.discs,
+            addr_length: v1.addr_length,
+            number_of_hashtables: v1.number_of_hashtables,
+            input_length: v1.mapping.len(),
+            mapping: v1.mapping,
+            last_rank: v1.last_rank,
+            rank_tables: v1.rank_tables,
+            bleach: v1.bleach,
+            ram: v1.ram,
+            seed: v1.seed,
+            address_mode: v1.address_mode,
+            remainder: RemainderPolicy::Drop,
+            mapping_strategy: MappingStrategy::Random,
+            unseen_policy: v1.unseen_policy,
+            nan_policy: v1.nan_policy,
+            policies: Policies::default(),
+            phantom: PhantomData,
+        })
+    }
+    // files from before the format, with hashtable rams only and no way to
+    // check the types they were written for
+    fn migrate_v0(stream: &[u8], max_bytes: u64) -> Result<Wisard<T, L>, WisardError>
+    where
+        L: DeserializeOwned + Eq + Hash,
+    {
+        if stream.len() as u64 > max_bytes {
+            return Err(WisardError::WisardLimitExceeded("bytes"));
+        }
+        let legacy: LegacyWisard<L> = match format::options(max_bytes).deserialize(stream) {
+            Ok(legacy) => legacy,
+            Err(_) => return Err(WisardError::WisardUnknownFormat),
+        };
+        let n = legacy.number_of_hashtables;
+        if legacy.mapping.len() != n as usize * legacy.addr_length as usize
+            || legacy
+                .discs
+                .values()
+                .any(|d| d.number_of_hashtables != n || d.h_rams.len() != n as usize)
+        {
+            return Err(WisardError::WisardUnknownFormat);
+        }
+        let mut wis = Wisard::with_params(n, legacy.addr_length, legacy.bleach);
+        wis.mapping = legacy.mapping;
+        wis.last_rank = legacy.last_rank;
+        wis.rank_tables = legacy.rank_tables;
+        wis.discs = legacy
+            .discs
+            .into_iter()
+            .map(|(label, d)| {
+                let disc = Discriminator {
+                    number_of_hashtables: d.number_of_hashtables,
+                    h_rams: Rams::HashTable(d.h_rams),
+                    times_trained: d.times_trained,
+                };
+                (label, disc)
+            })
+            .collect();
+        Ok(wis)
+    }
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), WisardError>
+    where
+        T: TypeTag,
+        L: Serialize + Eq + Hash + TypeTag,
+    {
+        let mut file = File::create(path)?;
+        file.write_all(&self.save()?)?;
+        Ok(())
+    }
+    pub fn load_from_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), WisardError>
+    where
+        T: TypeTag,
+        L: DeserializeOwned + Eq + Hash + TypeTag,
+    {
+        let stream = std::fs::read(path)?;
+        self.load(&stream)
+    }
+    pub fn to_document(&self) -> ModelDocument<L>
+    where
+        T: TypeTag,
+        L: Ord + Clone + TypeTag,
+    {
+        let mut rank_tables: Vec<RankDocument> = self
+            .rank_tables
+            .iter()
+            .map(|(permutation, &rank)| RankDocument {
+                permutation: permutation.clone(),
+                rank,
+            })
+            .collect();
+        rank_tables.sort_by_key(|r| r.rank);
+        let mut classes: Vec<ClassDocument<L>> = self
+            .discs
+            .iter()
+            .map(|(label, disc)| ClassDocument {
+                label: label.clone(),
+                discriminator: disc.to_document(),
+            })
+            .collect();
+        classes.sort_by(|a, b| a.label.cmp(&b.label));
+        let mut class_weights: Vec<(L, f64)> = self
+            .policies
+            .class_weights
+            .iter()
+            .map(|(label, weight)| (label.clone(), *weight))
+            .collect();
+        class_weights.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut label_thresholds: Vec<(L, f64)> = self
+            .policies
+            .label_thresholds
+            .iter()
+            .map(|(label, threshold)| (label.clone(), *threshold))
+            .collect();
+        label_thresholds.sort_by(|a, b| a.0.cmp(&b.0));
+        ModelDocument {
+            model: MODEL_NAME.to_string(),
+            element_type: T::TAG.to_string(),
+            label_type: L::TAG.to_string(),
+            number_of_hashtables: self.number_of_hashtables,
+            addr_length: self.addr_length,
+            bleach: self.bleach,
+            input_length: self.input_length,
+            remainder: self.remainder.clone(),
+            mapping_strategy: self.mapping_strategy.clone(),
+            bleach_policy: self.policies.bleach_policy.clone(),
+            tie_breaker: self.policies.tie_breaker.clone(),
+            normalization: self.policies.normalization.clone(),
+            class_weights,
+            reject_rule: self.policies.reject_rule.clone(),
+            label_thresholds,
+            mapping: self.mapping.clone(),
+            last_rank: self.last_rank,
+            rank_tables,
+            ram: self.ram.clone(),
+            seed: self.seed,
+            address_mode: self.address_mode.clone(),
+            unseen_policy: self.unseen_policy.clone(),
+            nan_policy: self.nan_policy.clone(),
+            classes,
+        }
+    }
+
+    // the model of a document, checked like load_bounded does
+    pub fn from_document(
+        document: ModelDocument<L>,
+        limits: &LoadLimits,
+    ) -> Result<Wisard<T, L>, WisardError>
+    where
+        T: TypeTag,
+        L: Eq + Hash + TypeTag,
+    {
+        format::expect_types::<T, L>(
+            MODEL_NAME,
+            &document.model,
+            &document.element_type,
+            &document.label_type,
+        )?;
+        let wis = Wisard {
+            discs: document
+                .classes
+                .into_iter()
+                .map(|c| (c.label, Discriminator::from_document(c.discriminator)))
+                .collect(),
+            addr_length: document.addr_length,
+            number_of_hashtables: document.number_of_hashtables,
+            input_length: document.input_length,
+            mapping: document.mapping,
+            last_rank: document.last_rank,
+            rank_tables: document
+                .rank_tables
+                .into_iter()
+                .map(|r| (r.permutation, r.rank))
+                .collect(),
+            bleach: document.bleach,
+            ram: document.ram,
+            seed: document.seed,
+            address_mode: document.address_mode,
+            remainder: document.remainder,
+            mapping_strategy: document.mapping_strategy,
+            unseen_policy: document.unseen_policy,
+            nan_policy: document.nan_policy,
+            policies: Policies {
+                bleach_policy: document.bleach_policy,
+                tie_breaker: document.tie_breaker,
+                normalization: document.normalization,
+                class_weights: document.class_weights.into_iter().collect(),
+                reject_rule: document.reject_rule,
+                label_thresholds: document.label_thresholds.into_iter().collect(),
+            },
+            phantom: PhantomData,
+        };
+        wis.validate(limits)?;
+        Ok(wis)
+    }
+
+    pub fn export(&self, format: &ExportFormat) -> Result<Vec<u8>, WisardError>
+    where
+        T: TypeTag,
+        L: Serialize + Ord + Clone + TypeTag,
+    {
+        format.encode(&self.to_document())
+    }
+
+    // import checks the structure of the model, import_bounded also its size
+    pub fn import(&mut self, stream: &[u8], format: &ExportFormat) -> Result<(), WisardError>
+    where
+        T: TypeTag,
+        L: DeserializeOwned + Eq + Hash + TypeTag,
+    {
+        self.import_bounded(stream, format, &LoadLimits::unlimited())
+    }
+
+    pub fn import_bounded(
+        &mut self,
+        stream: &[u8],
+        format: &ExportFormat,
+        limits: &LoadLimits,
+    ) -> Result<(), WisardError>
+    where
+        T: TypeTag,
+        L: DeserializeOwned + Eq + Hash + TypeTag,
+    {
+        if stream.len() as u64 > limits.max_bytes {
+            return Err(WisardError::WisardLimitExceeded("bytes"));
+        }
+        let document: ModelDocument<L> = format.decode(stream)?;
+        let decoded = Self::from_document(document, limits)?;
+        self.replace_with(decoded);
         Ok(())
     }
-    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), WisardError> {
-        let mut file = match File::create(path) {
-            Ok(f) => f,
-            Err(_) => return Err(WisardError::WisardIOError),
-        };
-        match bincode::serialize_into(&mut file, &self) {
-            Ok(_) => Ok(()),
-            Err(_) => return Err(WisardError::WisardValidationFailed),
+
+    // the mapping alone, for other models of the same sizes to import
+    pub fn export_mapping(&self, format: &ExportFormat) -> Result<Vec<u8>, WisardError> {
+        format.encode(&MappingDocument {
+            input_length: self.input_length,
+            addr_length: self.addr_length,
+            number_of_hashtables: self.number_of_hashtables,
+            remainder: self.remainder.clone(),
+            mapping: self.mapping.clone(),
+        })
+    }
+
+    // replaces the mapping with an exported one, which erases the model since
+    // its training was done with the old mapping
+    pub fn import_mapping(
+        &mut self,
+        stream: &[u8],
+        format: &ExportFormat,
+    ) -> Result<(), WisardError> {
+        let document: MappingDocument = format.decode(stream)?;
+        if document.input_length != self.input_length
+            || document.addr_length != self.addr_length
+            || document.number_of_hashtables != self.number_of_hashtables
+            || document.remainder != self.remainder
+        {
+            return Err(WisardError::WisardIncompatible);
         }
-    }
-    pub fn load_from_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), WisardError> {
-        let file = match File::open(path) {
-            Ok(f) => f,
-            Err(_) => return Err(WisardError::WisardIOError),
-        };
-        let decoded: Wisard<T> = match bincode::deserialize_from(file) {
-            Ok(d) => d,
-            Err(_) => return Err(WisardError::WisardValidationFailed),
-        };
-        self.discs = decoded.discs;
-        self.addr_length = decoded.addr_length;
-        self.number_of_hashtables = decoded.number_of_hashtables;
-        self.mapping = decoded.mapping;
-        self.last_rank = decoded.last_rank;
-        self.rank_tables = decoded.rank_tables;
-        self.bleach = decoded.bleach;
+        mapping::check_mapping(
+            &document.mapping,
+            self.input_length,
+            self.number_of_hashtables as usize,
+            self.addr_length as usize,
+            &self.remainder,
+        )?;
+        self.mapping_strategy = MappingStrategy::Custom(document.mapping);
+        self.erase();
         Ok(())
     }
+
     pub fn erase(&mut self) {
-        self.mapping.shuffle(&mut thread_rng());
+        self.mapping = self.mapping_strategy.mapping(
+            self.input_length,
+            self.number_of_hashtables as usize,
+            self.addr_length as usize,
+            &self.remainder,
+            self.seed,
+        );
         self.discs = HashMap::new();
         self.last_rank = 0;
         self.rank_tables = HashMap::new()
@@ -286,28 +2411,28 @@ mod lib_tests {
     #[test]
     fn test_lib_ranks() {
         // this test verifies that ranks is able to push address to rank_tables
-        let mut wis = Wisard::new();
+        let mut wis: Wisard<i32> = Wisard::new();
         let samples = vec![
             52, 70, 64, 199, 7, 133, 5, 194, 16, 104, 41, 147, 42, 77, 188, 140, 148, 160, 6, 87,
             107, 73, 168, 95, 63, 11, 2, 49, 130, 43, 92, 110, 13, 157, 125, 6, 93, 119, 86, 85,
             103, 27, 124, 65, 9, 195, 21, 130, 192, 32, 136, 34, 70, 89, 84, 167, 175, 148, 116,
             177, 161, 134, 98, 30, 190, 47,
         ];
-        wis.ranks_t(samples.iter().collect());
+        wis.ranks_t(&samples);
         assert!(!wis.rank_tables.is_empty());
     }
     #[test]
     fn test_lib_rank_table_length() {
         // this test ensures that the same addresses aren't pushed into the rank_tables
         // repeatedly
-        let mut wis = Wisard::new();
+        let mut wis: Wisard<i32> = Wisard::new();
         let samples = vec![
             52, 70, 64, 199, 7, 133, 5, 194, 16, 104, 41, 147, 42, 77, 188, 140, 148, 160, 6, 87,
             107, 73, 168, 95, 63, 11, 2, 49, 130, 43, 92, 110, 13, 157, 125, 6, 93, 119, 86, 85,
             103, 27, 124, 65, 9, 195, 21, 130, 192, 32, 136, 34, 70, 89, 84, 167, 175, 148, 116,
             177, 161, 134, 98, 30, 190, 47,
         ];
-        wis.ranks_t(samples.iter().collect());
+        wis.ranks_t(&samples);
         let length1 = wis.rank_tables.len();
         let samples = vec![
             52, 70, 64, 199, 7, 133, 5, 194, 16, 104, 41, 147, 42, 77, 188, 140, 148, 160, 6, 87,
@@ -315,7 +2440,7 @@ mod lib_tests {
             103, 27, 124, 65, 9, 195, 21, 130, 192, 32, 136, 34, 70, 89, 84, 167, 175, 148, 116,
             177, 161, 134, 98, 30, 190, 47,
         ];
-        wis.ranks_t(samples.iter().collect());
+        wis.ranks_t(&samples);
         let length2 = wis.rank_tables.len();
         assert_eq!(length1, length2);
     }
@@ -323,27 +2448,27 @@ mod lib_tests {
     #[test]
     fn test_lib_rank_addresses() {
         // this test verifies that for each new piece of data, a correct rank is attributed
-        let mut wis = Wisard::new();
+        let mut wis: Wisard<i32> = Wisard::new();
         let samples = vec![
             52, 70, 64, 199, 7, 133, 5, 194, 16, 104, 41, 147, 42, 77, 188, 140, 148, 160, 6, 87,
             107, 73, 168, 95, 63, 11, 2, 49, 130, 43, 92, 110, 13, 157, 125, 6, 93, 119, 86, 85,
             103, 27, 124, 65, 9, 195, 21, 130, 192, 32, 136, 34, 70, 89, 84, 167, 175, 148, 116,
             177, 161, 134, 98, 30, 190, 47,
         ];
-        let addresses = wis.ranks_t(samples.iter().collect());
+        let addresses = wis.ranks_t(&samples);
         assert_eq!(addresses, vec![0, 1, 2]);
     }
     #[test]
     fn test_lib_rank_different_addresses() {
         // this test verifies that small changes in data get close addresses
-        let mut wis = Wisard::new();
+        let mut wis: Wisard<i32> = Wisard::new();
         let samples = vec![
             52, 70, 64, 199, 7, 133, 5, 194, 16, 104, 41, 147, 42, 77, 188, 140, 148, 160, 6, 87,
             107, 73, 168, 95, 63, 11, 2, 49, 130, 43, 92, 110, 13, 157, 125, 6, 93, 119, 86, 85,
             103, 27, 124, 65, 9, 195, 21, 130, 192, 32, 136, 34, 70, 89, 84, 167, 175, 148, 116,
             177, 161, 134, 98, 30, 190, 47,
         ];
-        let addresses = wis.ranks_t(samples.iter().collect());
+        let addresses = wis.ranks_t(&samples);
         assert_eq!(addresses, vec![0, 1, 2]);
         let samples = vec![
             52, 70, 64, 199, 7, 133, 5, 194, 16, 104, 41, 147, 42, 77, 188, 140, 148, 160, 6, 87,
@@ -351,7 +2476,7 @@ mod lib_tests {
             103, 27, 124, 65, 9, 195, 21, 130, 192, 32, 136, 34, 70, 89, 84, 167, 175, 148, 116,
             177, 161, 134, 98, 30, 190, 205,
         ];
-        let addresses = wis.ranks_t(samples.iter().collect());
+        let addresses = wis.ranks_t(&samples);
         assert_eq!(addresses, vec![0, 1, 3]);
     }
 
@@ -360,18 +2485,18 @@ mod lib_tests {
         use std::fs;
         fs::create_dir_all("weights/").unwrap();
 
-        let mut wis = Wisard::new();
+        let mut wis: Wisard<i32> = Wisard::new();
         let samples = vec![
             52, 70, 64, 199, 7, 133, 5, 194, 16, 104, 41, 147, 42, 77, 188, 140, 148, 160, 6, 87,
             107, 73, 168, 95, 63, 11, 2, 49, 130, 43, 92, 110, 13, 157, 125, 6, 93, 119, 86, 85,
             103, 27, 124, 65, 9, 195, 21, 130, 192, 32, 136, 34, 70, 89, 84, 167, 175, 148, 116,
             177, 161, 134, 98, 30, 190, 47,
         ];
-        let _ = wis.ranks_t(samples.iter().collect());
+        let _ = wis.ranks_t(&samples);
 
         wis.save_to_file("weights/weigths_u8.bin").unwrap();
 
-        let mut decoded = Wisard::new();
+        let mut decoded: Wisard<i32> = Wisard::new();
         decoded.load_from_file("weights/weigths_u8.bin").unwrap();
         let samples = vec![
             52, 70, 64, 199, 7, 133, 5, 194, 16, 104, 41, 147, 42, 77, 188, 140, 148, 160, 6, 87,
@@ -379,7 +2504,7 @@ mod lib_tests {
             103, 27, 124, 65, 9, 195, 21, 130, 192, 32, 136, 34, 70, 89, 84, 167, 175, 148, 116,
             177, 161, 134, 98, 30, 190, 205,
         ];
-        let decoded_addresses = decoded.ranks_t(samples.iter().collect());
+        let decoded_addresses = decoded.ranks_t(&samples);
 
         println!("{:?}", decoded_addresses);
 
@@ -389,16 +2514,173 @@ mod lib_tests {
         assert_eq!(vec![0, 1, 3], decoded_addresses);
     }
 
+    #[test]
+    fn test_load_rejections() {
+        let mut wis = Wisard::<u8>::with_params(2, 3, 0);
+        wis.train(&[1, 2, 3, 4, 5, 6], "up".to_string()).unwrap();
+        let mut encoded = wis.save().unwrap();
+
+        let mut other = Wisard::<u16>::new();
+        match other.load(&encoded) {
+            Err(WisardError::WisardTypeMismatch { expected, found }) => {
+                assert_eq!(expected, "u16");
+                assert_eq!(found, "u8");
+            }
+            _ => panic!("a u8 model loaded as u16"),
+        }
+        let mut other = Wisard::<u8, u8>::new();
+        assert!(other.load(&encoded).is_err());
+
+        let last = encoded.len() - 1;
+        encoded[last] ^= 1;
+        let mut other = Wisard::<u8>::new();
+        assert!(matches!(
+            other.load(&encoded),
+            Err(WisardError::WisardChecksumMismatch)
+        ));
+        assert!(matches!(
+            other.load(b"not a model"),
+            Err(WisardError::WisardUnknownFormat)
+        ));
+        assert_eq!(other.get_info(), (28, 28, 0));
+    }
+
+    #[test]
+    fn test_load_bounded() {
+        let mut wis = Wisard::<u8>::with_params(2, 3, 0);
+        wis.train(&[1, 2, 3, 4, 5, 6], "up".to_string()).unwrap();
+        wis.train(&[6, 5, 4, 3, 2, 1], "down".to_string()).unwrap();
+        let encoded = wis.save().unwrap();
+
+        let mut other = Wisard::<u8>::new();
+        other
+            .load_bounded(&encoded, &LoadLimits::default())
+            .unwrap();
+        assert_eq!(other.classify(&[6, 5, 4, 3, 2, 1]).unwrap(), "down");
+
+        let limits = [
+            (
+                LoadLimits {
+                    max_bytes: 64,
+                    ..LoadLimits::default()
+                },
+                "bytes",
+            ),
+            (
+                LoadLimits {
+                    max_labels: 1,
+                    ..LoadLimits::default()
+                },
+                "labels",
+            ),
+            (
+                LoadLimits {
+                    max_rank_entries: 1,
+                    ..LoadLimits::default()
+                },
+                "rank entries",
+            ),
+            (
+                LoadLimits {
+                    max_ram_entries: 3,
+                    ..LoadLimits::default()
+                },
+                "RAM entries",
+            ),
+        ];
+        for (limits, name) in limits.iter() {
+            match other.load_bounded(&encoded, limits) {
+                Err(WisardError::WisardLimitExceeded(limit)) => assert_eq!(limit, *name),
+                _ => panic!("the {} limit was not enforced", name),
+            }
+        }
+    }
+
+    #[test]
+    fn test_load_bounded_validation() {
+        let mut wis = Wisard::<u8>::with_params(2, 3, 0);
+        wis.train(&[1, 2, 3, 4, 5, 6], "up".to_string()).unwrap();
+        let mut other = Wisard::<u8>::new();
+
+        // a mapping that repeats an input is not a permutation
+        let mapping = wis.mapping.clone();
+        wis.mapping[0] = wis.mapping[1];
+        let encoded = wis.save().unwrap();
+        assert!(matches!(
+            other.load_bounded(&encoded, &LoadLimits::default()),
+            Err(WisardError::WisardInvalidModel(_))
+        ));
+        other.load(&encoded).unwrap();
+        wis.mapping = mapping;
+
+        // rank tables pointing past the addresses of a tuple
+        wis.rank_tables.insert(vec![0, 1, 7], 0);
+        let encoded = wis.save().unwrap();
+        assert!(matches!(
+            other.load_bounded(&encoded, &LoadLimits::default()),
+            Err(WisardError::WisardInvalidModel(_))
+        ));
+        wis.rank_tables.remove(&vec![0, 1, 7]);
+
+        // RAMs that don't match number_of_hashtables
+        wis.number_of_hashtables = 1;
+        wis.mapping.truncate(3);
+        let encoded = wis.save().unwrap();
+        assert!(matches!(
+            other.load_bounded(&encoded, &LoadLimits::default()),
+            Err(WisardError::WisardInvalidModel(_))
+        ));
+    }
+
+    #[test]
+    fn test_load_legacy() {
+        let mut wis = Wisard::<u8>::with_params(2, 3, 0);
+        wis.train(&[1, 2, 3, 4, 5, 6], "up".to_string()).unwrap();
+        wis.train(&[6, 5, 4, 3, 2, 1], "down".to_string()).unwrap();
+        let legacy = LegacyWisard {
+            discs: wis
+                .discs
+                .iter()
+                .map(|(label, d)| {
+                    let h_rams = match &d.h_rams {
+                        Rams::HashTable(rams) => rams.clone(),
+                        _ => unreachable!(),
+                    };
+                    let disc = LegacyDiscriminator {
+                        number_of_hashtables: d.number_of_hashtables,
+                        h_rams,
+                        times_trained: d.times_trained,
+                    };
+                    (label.clone(), disc)
+                })
+                .collect(),
+            addr_length: 3,
+            number_of_hashtables: 2,
+            mapping: wis.mapping.clone(),
+            last_rank: wis.last_rank,
+            rank_tables: wis.rank_tables.clone(),
+            bleach: 0,
+        };
+        let stream = bincode::serialize(&legacy).unwrap();
+
+        let mut migrated = Wisard::<u8>::new();
+        migrated.load(&stream).unwrap();
+        assert_eq!(migrated.get_info(), (2, 3, 0));
+        assert_eq!(migrated.mapping, wis.mapping);
+        assert_eq!(migrated.classify(&[1, 2, 3, 4, 5, 6]).unwrap(), "up");
+        assert_eq!(migrated.classify(&[6, 5, 4, 3, 2, 1]).unwrap(), "down");
+    }
+
     #[test]
     fn test_erase() {
-        let mut wis = Wisard::new();
+        let mut wis: Wisard<i32> = Wisard::new();
         let samples = vec![
             52, 70, 64, 199, 7, 133, 5, 194, 16, 104, 41, 147, 42, 77, 188, 140, 148, 160, 6, 87,
             107, 73, 168, 95, 63, 11, 2, 49, 130, 43, 92, 110, 13, 157, 125, 6, 93, 119, 86, 85,
             103, 27, 124, 65, 9, 195, 21, 130, 192, 32, 136, 34, 70, 89, 84, 167, 175, 148, 116,
             177, 161, 134, 98, 30, 190, 47,
         ];
-        let _ = wis.ranks_t(samples.iter().collect());
+        let _ = wis.ranks_t(&samples);
 
         wis.erase();
 
@@ -408,8 +2690,1061 @@ mod lib_tests {
             103, 27, 124, 65, 9, 195, 21, 130, 192, 32, 136, 34, 70, 89, 84, 167, 175, 148, 116,
             177, 161, 134, 98, 30, 190, 205,
         ];
-        let decoded_addresses = wis.ranks_t(samples.iter().collect());
+        let decoded_addresses = wis.ranks_t(&samples);
 
         assert_eq!(vec![0, 1, 2], decoded_addresses);
     }
+
+    #[test]
+    fn test_classify_with_scores() {
+        let mut wis = Wisard::with_params(2, 3, 0);
+        wis.train(&[1, 2, 3, 4, 5, 6], "up".to_string()).unwrap();
+        wis.train(&[6, 5, 4, 3, 2, 1], "down".to_string()).unwrap();
+
+        let prediction = wis.classify_with_scores(&[1, 2, 3, 4, 5, 6]).unwrap();
+
+        assert_eq!(prediction.label, "up");
+        assert_eq!(prediction.scores.len(), 2);
+        assert_eq!(prediction.scores[0].votes, 2);
+        assert_eq!(prediction.scores[0].score, 1.0);
+        assert_eq!(prediction.scores[0].times_trained, 1);
+        assert_eq!(prediction.scores[1].label, "down");
+        assert_eq!(prediction.scores[1].votes, 0);
+        assert_eq!(prediction.margin, 1.0);
+    }
+
+    #[test]
+    fn test_bloom_rams() {
+        let kinds = vec![
+            RamKind::Bloom {
+                size: 512,
+                hashes: 3,
+            },
+            RamKind::CountingBloom {
+                size: 512,
+                hashes: 3,
+            },
+        ];
+        for ram in kinds {
+            let mut wis = Wisard::with_ram(2, 3, 0, ram, None);
+            wis.train(&[1, 2, 3, 4, 5, 6], "up".to_string()).unwrap();
+            wis.train(&[6, 5, 4, 3, 2, 1], "down".to_string()).unwrap();
+
+            assert_eq!(wis.classify(&[1, 2, 3, 4, 5, 6]).unwrap(), "up");
+            assert_eq!(wis.classify(&[6, 5, 4, 3, 2, 1]).unwrap(), "down");
+        }
+    }
+
+    #[test]
+    fn test_counting_bloom_bleach() {
+        let ram = RamKind::CountingBloom {
+            size: 512,
+            hashes: 3,
+        };
+        let mut wis = Wisard::with_ram(2, 3, 1, ram, None);
+        wis.train(&[1, 2, 3, 4, 5, 6], "up".to_string()).unwrap();
+        let prediction = wis.classify_with_scores(&[1, 2, 3, 4, 5, 6]).unwrap();
+        assert_eq!(prediction.scores[0].votes, 0);
+
+        wis.train(&[1, 2, 3, 4, 5, 6], "up".to_string()).unwrap();
+        let prediction = wis.classify_with_scores(&[1, 2, 3, 4, 5, 6]).unwrap();
+        assert_eq!(prediction.scores[0].votes, 2);
+    }
+
+    #[test]
+    fn test_seeded_mapping() {
+        let mut wis1 = Wisard::<u8>::with_seed(28, 28, 0, 42);
+        let wis2 = Wisard::<u8>::with_seed(28, 28, 0, 42);
+        let wis3 = Wisard::<u8>::with_seed(28, 28, 0, 43);
+        assert_eq!(wis1.mapping, wis2.mapping);
+        assert_ne!(wis1.mapping, wis3.mapping);
+
+        wis1.erase();
+        assert_eq!(wis1.mapping, wis2.mapping);
+
+        wis1.erase_and_change_hyperparameters(28, 28, 0, Some(43));
+        assert_eq!(wis1.mapping, wis3.mapping);
+    }
+
+    #[test]
+    fn test_train_batch() {
+        let data = vec![
+            vec![1, 2, 3, 4, 5, 6],
+            vec![6, 5, 4, 3, 2, 1],
+            vec![10, 20, 30, 40, 50, 60],
+            vec![60, 50, 40, 30, 20, 10],
+        ];
+        let labels = vec![
+            "up".to_string(),
+            "down".to_string(),
+            "up".to_string(),
+            "down".to_string(),
+        ];
+
+        let mut serial = Wisard::<u8>::with_seed(2, 3, 0, 7);
+        for (d, l) in data.iter().zip(labels.iter()) {
+            serial.train(d, l.clone()).unwrap();
+        }
+        let mut batch = Wisard::<u8>::with_seed(2, 3, 0, 7);
+        batch.train_batch(&data, &labels).unwrap();
+
+        assert_eq!(serial.rank_tables, batch.rank_tables);
+        assert_eq!(serial.last_rank, batch.last_rank);
+        assert_eq!(
+            serial.classify_with_scores(&data[2]).unwrap(),
+            batch.classify_with_scores(&data[2]).unwrap()
+        );
+        let serial_labels: Vec<String> = data.iter().map(|d| serial.classify(d).unwrap()).collect();
+        let batch_labels: Vec<String> = batch
+            .classify_batch(&data)
+            .unwrap()
+            .into_iter()
+            .map(|p| p.into_label().unwrap())
+            .collect();
+        assert_eq!(batch_labels, serial_labels);
+    }
+
+    #[test]
+    fn test_lehmer_code() {
+        assert_eq!(lehmer_code(&[0, 1, 2, 3]), 0);
+        assert_eq!(lehmer_code(&[0, 1, 3, 2]), 1);
+        assert_eq!(lehmer_code(&[3, 2, 1, 0]), 23);
+        assert_eq!(lehmer_code(&[1, 0, 2, 3]), 6);
+    }
+
+    #[test]
+    fn test_stateless_addresses() {
+        for mode in [AddressMode::Lehmer, AddressMode::Hash].iter() {
+            let data = vec![vec![1, 2, 3, 4, 5, 6], vec![6, 5, 4, 3, 2, 1]];
+            let labels = vec!["up".to_string(), "down".to_string()];
+
+            let mut serial = Wisard::<u8>::with_seed(2, 3, 0, 7).with_address_mode(mode.clone());
+            serial.train(&data[1], labels[1].clone()).unwrap();
+            serial.train(&data[0], labels[0].clone()).unwrap();
+            let mut batch = Wisard::<u8>::with_seed(2, 3, 0, 7).with_address_mode(mode.clone());
+            batch.train_batch(&data, &labels).unwrap();
+
+            assert!(batch.rank_tables.is_empty());
+            for d in data.iter() {
+                assert_eq!(
+                    serial.classify_with_scores(d).unwrap().scores[0],
+                    batch.classify_with_scores(d).unwrap().scores[0]
+                );
+            }
+            assert_eq!(batch.classify(&data[0]).unwrap(), "up");
+            assert_eq!(batch.classify(&data[1]).unwrap(), "down");
+        }
+    }
+
+    #[test]
+    fn test_unseen_policy() {
+        let mut wis = Wisard::<u8>::with_params(1, 4, 0);
+        wis.rank_t(&[0, 1, 2, 3]);
+        wis.rank_t(&[3, 2, 1, 0]);
+
+        assert_eq!(wis.rank_c(&[0, 1, 3, 2]), 3);
+        assert_eq!(wis.rank_c(&[2, 3, 1, 0]), 3);
+
+        wis.set_unseen_policy(UnseenPolicy::NeverVote);
+        assert_eq!(wis.rank_c(&[0, 1, 3, 2]), NO_VOTE);
+
+        wis.set_unseen_policy(UnseenPolicy::Hash);
+        let first = wis.rank_c(&[0, 1, 3, 2]);
+        let second = wis.rank_c(&[2, 3, 1, 0]);
+        assert_ne!(first, second);
+        assert!(first > wis.last_rank && second > wis.last_rank);
+        assert_eq!(wis.rank_c(&[0, 1, 2, 3]), 0);
+
+        wis.set_unseen_policy(UnseenPolicy::Nearest);
+        assert_eq!(wis.rank_c(&[0, 1, 3, 2]), 0);
+        assert_eq!(wis.rank_c(&[2, 3, 1, 0]), 1);
+    }
+
+    #[test]
+    fn test_never_vote() {
+        let mut wis = Wisard::<u8>::with_params(2, 3, 0);
+        wis.train(&[1, 2, 3, 4, 5, 6], "up".to_string()).unwrap();
+        wis.set_unseen_policy(UnseenPolicy::NeverVote);
+        let prediction = wis.classify_with_scores(&[6, 5, 4, 3, 2, 1]).unwrap();
+        assert_eq!(prediction.scores[0].votes, 0);
+    }
+
+    #[test]
+    fn test_nan_policy() {
+        let mut wis = Wisard::<f32>::with_params(2, 3, 0);
+        wis.train(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0], "up".to_string())
+            .unwrap();
+
+        let nan = [1.0, f32::NAN, 3.0, 4.0, 5.0, 6.0];
+        assert!(matches!(
+            wis.train(&nan, "up".to_string()),
+            Err(WisardError::WisardIncomparable)
+        ));
+        assert!(matches!(
+            wis.classify(&nan),
+            Err(WisardError::WisardIncomparable)
+        ));
+
+        wis.set_nan_policy(NanPolicy::First);
+        wis.train(&nan, "up".to_string()).unwrap();
+
+        // tuples of a single value are rejected too
+        let mut wis = Wisard::<f32>::with_params(2, 1, 0);
+        assert!(matches!(
+            wis.train(&[1.0, f32::NAN], "up".to_string()),
+            Err(WisardError::WisardIncomparable)
+        ));
+        let config = WisardConfig::new()
+            .input_length(3)
+            .addr_length(2)
+            .seed(3)
+            .remainder(RemainderPolicy::Shorter);
+        let wis = Wisard::<f32>::with_config(&config).unwrap();
+        for i in 0..3 {
+            let mut sample = [1.0, 2.0, 3.0];
+            sample[i] = f32::NAN;
+            assert!(matches!(
+                wis.classify(&sample),
+                Err(WisardError::WisardIncomparable)
+            ));
+        }
+
+        // NaN sorts before 1 and 2
+        let mut wis = Wisard::<f32>::with_params(1, 3, 0);
+        wis.set_nan_policy(NanPolicy::First);
+        assert_eq!(wis.ranks_t(&[2.0, f32::NAN, 1.0]), vec![0]);
+        assert_eq!(wis.ranks_t(&[1.0, 2.0, f32::NAN]), vec![1]);
+        assert_eq!(wis.rank_tables.get(&vec![1, 2, 0]), Some(&0));
+
+        // NaN sorts after 1 and 2, so the first sample ranks like the old second one
+        wis.set_nan_policy(NanPolicy::Last);
+        assert_eq!(wis.ranks_t(&[2.0, f32::NAN, 1.0]), vec![1]);
+        assert_eq!(wis.ranks_t(&[1.0, 2.0, f32::NAN]), vec![2]);
+
+        // negative NaN below everything, positive NaN above, -0 before 0
+        let mut wis = Wisard::<f64>::with_params(1, 4, 0);
+        wis.set_nan_policy(NanPolicy::TotalOrder);
+        assert_eq!(wis.ranks_t(&[0.0, -f64::NAN, f64::NAN, -0.0]), vec![0]);
+        assert_eq!(wis.rank_tables.get(&vec![1, 3, 0, 2]), Some(&0));
+        let extremes = [f64::INFINITY, f64::NAN, f64::NEG_INFINITY, -f64::NAN];
+        assert_eq!(wis.ranks_t(&extremes), vec![1]);
+        assert_eq!(wis.rank_tables.get(&vec![3, 2, 0, 1]), Some(&1));
+
+        assert_eq!(1.5f32.rank_cmp(&-0.0), Ordering::Greater);
+        assert_eq!((-0.0f32).rank_cmp(&0.0), Ordering::Less);
+        assert_eq!(f32::NAN.rank_cmp(&f32::INFINITY), Ordering::Greater);
+        assert_eq!((-f32::NAN).rank_cmp(&f32::NEG_INFINITY), Ordering::Less);
+        assert_eq!(3u8.rank_cmp(&4), Ordering::Less);
+    }
+
+    #[test]
+    fn test_generic_labels() {
+        let mut wis = Wisard::<u8, u8>::with_params(2, 3, 0);
+        wis.train(&[1, 2, 3, 4, 5, 6], 1).unwrap();
+        wis.train(&[6, 5, 4, 3, 2, 1], 0).unwrap();
+        assert_eq!(wis.classify(&[1, 2, 3, 4, 5, 6]).unwrap(), 1);
+
+        let mut decoded = Wisard::<u8, u8>::with_params(2, 3, 0);
+        decoded.load(&wis.save().unwrap()).unwrap();
+        assert_eq!(decoded.classify(&[6, 5, 4, 3, 2, 1]).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_untrain() {
+        let mut wis = Wisard::<u8, &str>::with_params(2, 3, 0);
+        wis.train(&[1, 2, 3, 4, 5, 6], "up").unwrap();
+        wis.train(&[1, 2, 3, 6, 5, 4], "up").unwrap();
+        wis.train(&[6, 5, 4, 3, 2, 1], "down").unwrap();
+        let before = wis.classify_with_scores(&[1, 2, 3, 4, 5, 6]).unwrap();
+
+        wis.train(&[1, 2, 3, 4, 5, 6], "down").unwrap();
+        wis.untrain(&[1, 2, 3, 4, 5, 6], &"down").unwrap();
+        assert_eq!(
+            wis.classify_with_scores(&[1, 2, 3, 4, 5, 6]).unwrap(),
+            before
+        );
+
+        // nothing changes when the sample wasn't trained with that label
+        assert!(matches!(
+            wis.untrain(&[1, 2, 3, 4, 5, 6], &"down"),
+            Err(WisardError::WisardNotTrained)
+        ));
+        assert!(matches!(
+            wis.untrain(&[1, 2, 3, 4, 5, 6], &"left"),
+            Err(WisardError::WisardUnknownLabel)
+        ));
+        assert_eq!(
+            wis.classify_with_scores(&[1, 2, 3, 4, 5, 6]).unwrap(),
+            before
+        );
+
+        wis.untrain(&[6, 5, 4, 3, 2, 1], &"down").unwrap();
+        let prediction = wis.classify_with_scores(&[6, 5, 4, 3, 2, 1]).unwrap();
+        assert_eq!(prediction.scores.len(), 1);
+        assert_eq!(prediction.label, "up");
+    }
+
+    #[test]
+    fn test_untrain_rams() {
+        let ram = RamKind::Bloom {
+            size: 512,
+            hashes: 3,
+        };
+        let mut wis = Wisard::with_ram(2, 3, 0, ram, None);
+        wis.train(&[1, 2, 3, 4, 5, 6], "up".to_string()).unwrap();
+        assert!(matches!(
+            wis.untrain(&[1, 2, 3, 4, 5, 6], &"up".to_string()),
+            Err(WisardError::WisardUnsupported)
+        ));
+
+        let ram = RamKind::CountingBloom {
+            size: 512,
+            hashes: 3,
+        };
+        let mut wis = Wisard::with_ram(2, 3, 0, ram, None);
+        wis.train(&[1, 2, 3, 4, 5, 6], "up".to_string()).unwrap();
+        wis.train(&[1, 2, 3, 4, 5, 6], "up".to_string()).unwrap();
+        wis.train(&[6, 5, 4, 3, 2, 1], "up".to_string()).unwrap();
+        wis.untrain(&[1, 2, 3, 4, 5, 6], &"up".to_string()).unwrap();
+        let prediction = wis.classify_with_scores(&[1, 2, 3, 4, 5, 6]).unwrap();
+        assert_eq!(prediction.scores[0].votes, 2);
+        assert_eq!(prediction.scores[0].times_trained, 2);
+    }
+
+    #[test]
+    fn test_remove_class() {
+        let mut wis = Wisard::<u8, u8>::with_params(2, 3, 0);
+        wis.train(&[1, 2, 3, 4, 5, 6], 1).unwrap();
+        wis.train(&[6, 5, 4, 3, 2, 1], 0).unwrap();
+        wis.remove_class(&1).unwrap();
+        assert_eq!(wis.classify(&[1, 2, 3, 4, 5, 6]).unwrap(), 0);
+        assert!(matches!(
+            wis.remove_class(&1),
+            Err(WisardError::WisardUnknownLabel)
+        ));
+    }
+
+    #[test]
+    fn test_merge() {
+        let first: Vec<(Vec<u8>, &str)> = vec![
+            (vec![1, 2, 3, 4, 5, 6], "up"),
+            (vec![3, 2, 1, 6, 5, 4], "down"),
+        ];
+        let second: Vec<(Vec<u8>, &str)> = vec![
+            (vec![6, 5, 4, 3, 2, 1], "down"),
+            (vec![1, 3, 2, 4, 6, 5], "up"),
+            (vec![1, 2, 3, 4, 5, 6], "left"),
+        ];
+        let mut wis = Wisard::<u8, &str>::with_seed(2, 3, 0, 7);
+        let mut other = Wisard::<u8, &str>::with_seed(2, 3, 0, 7);
+        let mut union = Wisard::<u8, &str>::with_seed(2, 3, 0, 7);
+        for (data, label) in &first {
+            wis.train(data, label).unwrap();
+            union.train(data, label).unwrap();
+        }
+        for (data, label) in &second {
+            other.train(data, label).unwrap();
+            union.train(data, label).unwrap();
+        }
+
+        wis.merge(&other).unwrap();
+        assert_eq!(wis.rank_tables, union.rank_tables);
+        assert_eq!(wis.last_rank, union.last_rank);
+        for (data, _) in first.iter().chain(&second) {
+            let mut merged = wis.classify_with_scores(data).unwrap().scores;
+            let mut expected = union.classify_with_scores(data).unwrap().scores;
+            merged.sort_by_key(|s| s.label);
+            expected.sort_by_key(|s| s.label);
+            assert_eq!(merged, expected);
+        }
+
+        let different = Wisard::<u8, &str>::with_seed(2, 3, 0, 8);
+        assert!(matches!(
+            wis.merge(&different),
+            Err(WisardError::WisardIncompatible)
+        ));
+    }
+
+    #[test]
+    fn test_merge_bloom() {
+        let ram = RamKind::CountingBloom {
+            size: 512,
+            hashes: 3,
+        };
+        let mut wis = Wisard::<u8>::with_ram(2, 3, 1, ram.clone(), Some(7))
+            .with_address_mode(AddressMode::Lehmer);
+        let mut other =
+            Wisard::<u8>::with_ram(2, 3, 1, ram, Some(7)).with_address_mode(AddressMode::Lehmer);
+        wis.train(&[1, 2, 3, 4, 5, 6], "up".to_string()).unwrap();
+        other.train(&[1, 2, 3, 4, 5, 6], "up".to_string()).unwrap();
+        wis.merge(&other).unwrap();
+        let prediction = wis.classify_with_scores(&[1, 2, 3, 4, 5, 6]).unwrap();
+        assert_eq!(prediction.scores[0].votes, 2);
+        assert_eq!(prediction.scores[0].times_trained, 2);
+    }
+
+    #[test]
+    fn test_export_import() {
+        let mut wis = Wisard::<u8, u8>::with_params(2, 3, 0);
+        wis.train(&[1, 2, 3, 4, 5, 6], 1).unwrap();
+        wis.train(&[6, 5, 4, 3, 2, 1], 0).unwrap();
+
+        for format in [ExportFormat::Json, ExportFormat::MessagePack].iter() {
+            let exported = wis.export(format).unwrap();
+            let mut imported = Wisard::<u8, u8>::new();
+            imported.import(&exported, format).unwrap();
+            assert_eq!(imported.to_document(), wis.to_document());
+            assert_eq!(imported.export(format).unwrap(), exported);
+            assert_eq!(imported.classify(&[6, 5, 4, 3, 2, 1]).unwrap(), 0);
+
+            let mut other = Wisard::<u16, u8>::new();
+            assert!(matches!(
+                other.import(&exported, format),
+                Err(WisardError::WisardTypeMismatch { .. })
+            ));
+            assert!(matches!(
+                other.import(b"{", format),
+                Err(WisardError::WisardMalformed(_))
+            ));
+        }
+
+        let json = String::from_utf8(wis.export(&ExportFormat::Json).unwrap()).unwrap();
+        assert!(json.contains("\"label_type\": \"u8\""));
+        let document: ModelDocument<u8> = serde_json::from_str(&json).unwrap();
+        let labels: Vec<u8> = document.classes.iter().map(|c| c.label).collect();
+        assert_eq!(labels, vec![0, 1]);
+
+        let disc = &wis.discs[&1];
+        let exported = disc.export(&ExportFormat::MessagePack).unwrap();
+        let imported = Discriminator::import(&exported, &ExportFormat::MessagePack).unwrap();
+        assert_eq!(imported.to_document(), disc.to_document());
+    }
+
+    #[test]
+    fn test_error_kinds() {
+        let mut wis = Wisard::<u8, u8>::with_params(2, 3, 0);
+        assert!(matches!(
+            wis.classify(&[1, 2, 3, 4, 5, 6]),
+            Err(WisardError::WisardEmptyModel)
+        ));
+        match wis.train(&[1, 2, 3], 0) {
+            Err(WisardError::WisardInputTooShort { expected, actual }) => {
+                assert_eq!((expected, actual), (6, 3))
+            }
+            _ => panic!("a short input was trained"),
+        }
+        match wis.train_batch(&[vec![1, 2, 3, 4, 5, 6]], &[0, 1]) {
+            Err(WisardError::WisardLengthMismatch { expected, actual }) => {
+                assert_eq!((expected, actual), (1, 2))
+            }
+            _ => panic!("samples and labels of different lengths were trained"),
+        }
+
+        let error = wis.load_from_file("/nonexistent/model.wis").unwrap_err();
+        assert_eq!(error.code(), "io");
+        assert!(std::error::Error::source(&error).is_some());
+    }
+
+    #[test]
+    fn test_with_config() {
+        let config = WisardConfig::new()
+            .input_length(12)
+            .addr_length(3)
+            .bleach(1)
+            .seed(7)
+            .address_mode(AddressMode::Lehmer);
+        let mut wis = Wisard::<u8, u8>::with_config(&config).unwrap();
+        assert_eq!(wis.get_info(), (4, 3, 1));
+        assert_eq!(wis.get_seed(), Some(7));
+        assert_eq!(wis.get_address_mode(), &AddressMode::Lehmer);
+
+        let same = Wisard::<u8, u8>::with_seed(4, 3, 1, 7);
+        assert_eq!(wis.mapping, same.mapping);
+        wis.train(&[1; 12], 0).unwrap();
+
+        assert!(matches!(
+            Wisard::<u8, u8>::with_config(&config.addr_length(5)),
+            Err(WisardError::WisardInvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_remainder() {
+        let up: Vec<u8> = (0..25).collect();
+        let down: Vec<u8> = (0..25).rev().collect();
+        let policies = [
+            (RemainderPolicy::Pad, 3, 30),
+            (RemainderPolicy::Wrap, 3, 30),
+            (RemainderPolicy::Drop, 2, 20),
+            (RemainderPolicy::Shorter, 3, 25),
+        ];
+        for (remainder, hashtables, mapping_length) in policies.iter() {
+            let config = WisardConfig::new()
+                .input_length(25)
+                .addr_length(10)
+                .seed(3)
+                .remainder(remainder.clone());
+            let mut wis = Wisard::<u8, u8>::with_config(&config).unwrap();
+            assert_eq!(wis.get_info(), (*hashtables, 10, 0));
+            assert_eq!(wis.mapping.len(), *mapping_length);
+            assert_eq!(wis.get_input_length(), 25);
+
+            wis.train(&up, 0).unwrap();
+            wis.train(&down, 1).unwrap();
+            assert_eq!(wis.classify(&up).unwrap(), 0);
+            assert_eq!(wis.classify(&down).unwrap(), 1);
+            assert!(wis.validate(&LoadLimits::default()).is_ok());
+
+            let mut loaded = Wisard::<u8, u8>::new();
+            loaded
+                .load_bounded(&wis.save().unwrap(), &LoadLimits::default())
+                .unwrap();
+            assert_eq!(loaded.get_remainder(), remainder);
+            assert_eq!(loaded.classify(&down).unwrap(), 1);
+
+            // trailing values are not ignored
+            assert!(matches!(
+                wis.train(&[0; 26], 0),
+                Err(WisardError::WisardLengthMismatch {
+                    expected: 25,
+                    actual: 26
+                })
+            ));
+        }
+
+        // padding sorts before every value, in position order
+        let mut wis = Wisard::<u8, u8>::with_config(
+            &WisardConfig::new()
+                .input_length(2)
+                .addr_length(4)
+                .remainder(RemainderPolicy::Pad),
+        )
+        .unwrap();
+        wis.mapping = vec![3, 0, 2, 1];
+        let mut scratch = Scratch::new();
+        wis.permute_data(&[9, 5], &mut scratch).unwrap();
+        assert_eq!(scratch.permutation, vec![0, 2, 3, 1]);
+    }
+
+    #[test]
+    fn test_load_v1() {
+        let mut wis = Wisard::<u8>::with_params(2, 3, 0);
+        wis.train(&[1, 2, 3, 4, 5, 6], "up".to_string()).unwrap();
+        let mapping = wis.mapping.clone();
+        let v1 = WisardV1 {
+            discs: std::mem::take(&mut wis.discs),
+            addr_length: 3,
+            number_of_hashtables: 2,
+            mapping: wis.mapping.clone(),
+            last_rank: wis.last_rank,
+            rank_tables: wis.rank_tables.clone(),
+            bleach: 0,
+            ram: RamKind::HashTable,
+            seed: None,
+            address_mode: AddressMode::RankTable,
+            unseen_policy: UnseenPolicy::Shared,
+            nan_policy: NanPolicy::Reject,
+        };
+        let body = bincode::serialize(&v1).unwrap();
+        let header = Header::new::<u8, String>(MODEL_NAME, (2, 3, 0), &body);
+        let mut stream = format::MAGIC.to_vec();
+        stream.extend_from_slice(&1u16.to_le_bytes());
+        stream.extend(bincode::serialize(&header).unwrap());
+        stream.extend(body);
+
+        let mut migrated = Wisard::<u8>::new();
+        migrated
+            .load_bounded(&stream, &LoadLimits::default())
+            .unwrap();
+        assert_eq!(migrated.get_input_length(), 6);
+        assert_eq!(migrated.mapping, mapping);
+        assert_eq!(migrated.classify(&[1, 2, 3, 4, 5, 6]).unwrap(), "up");
+    }
+
+    #[test]
+    fn test_policies_section() {
+        // a section written with fewer policies than there are now
+        #[derive(Serialize)]
+        struct OlderPolicies {
+            tie_breaker: TieBreaker,
+        }
+        let older = rmp_serde::to_vec_named(&OlderPolicies {
+            tie_breaker: TieBreaker::MostTrained,
+        })
+        .unwrap();
+        let policies: Policies<String> = rmp_serde::from_slice(&older).unwrap();
+        assert_eq!(policies.tie_breaker, TieBreaker::MostTrained);
+        assert_eq!(policies.bleach_policy, BleachPolicy::Fixed);
+        assert_eq!(policies.reject_rule, RejectRule::default());
+
+        let mut wis = Wisard::<u8>::with_params(2, 3, 0);
+        wis.train(&[1, 2, 3, 4, 5, 6], "up".to_string()).unwrap();
+        wis.set_bleach_policy(BleachPolicy::Dynamic);
+        wis.set_class_weight("up".to_string(), 2.0).unwrap();
+        let mut loaded = Wisard::<u8>::new();
+        loaded.load(&wis.save().unwrap()).unwrap();
+        assert_eq!(loaded.get_bleach_policy(), &BleachPolicy::Dynamic);
+        assert_eq!(loaded.policies.class_weights, wis.policies.class_weights);
+    }
+
+    #[test]
+    fn test_mapping_strategies() {
+        let config = WisardConfig::new()
+            .input_length(16)
+            .addr_length(4)
+            .mapping(MappingStrategy::Contiguous);
+        let mut wis = Wisard::<u8, u8>::with_config(&config).unwrap();
+        assert_eq!(wis.mapping, (0..16).collect::<Vec<u64>>());
+        wis.erase();
+        assert_eq!(wis.mapping, (0..16).collect::<Vec<u64>>());
+
+        let patches = MappingStrategy::Patches {
+            width: 4,
+            height: 4,
+            patch_width: 2,
+            patch_height: 2,
+        };
+        let mut wis =
+            Wisard::<u8, u8>::with_config(&config.clone().mapping(patches.clone()).seed(5))
+                .unwrap();
+        let up: Vec<u8> = (0..16).collect();
+        wis.train(&up, 0).unwrap();
+        let mut loaded = Wisard::<u8, u8>::new();
+        loaded
+            .load_bounded(&wis.save().unwrap(), &LoadLimits::default())
+            .unwrap();
+        assert_eq!(loaded.get_mapping_strategy(), &patches);
+        assert_eq!(loaded.classify(&up).unwrap(), 0);
+
+        // a second model takes the mapping of the first
+        let exported = wis.export_mapping(&ExportFormat::Json).unwrap();
+        let mut shared = Wisard::<u8, u8>::with_config(&config).unwrap();
+        shared.train(&up, 1).unwrap();
+        shared
+            .import_mapping(&exported, &ExportFormat::Json)
+            .unwrap();
+        assert_eq!(shared.mapping, wis.mapping);
+        assert_eq!(
+            shared.get_mapping_strategy(),
+            &MappingStrategy::Custom(wis.mapping.clone())
+        );
+        assert!(matches!(
+            shared.classify(&up),
+            Err(WisardError::WisardEmptyModel)
+        ));
+        shared.erase();
+        assert_eq!(shared.mapping, wis.mapping);
+
+        let mut other = Wisard::<u8, u8>::with_params(2, 8, 0);
+        assert!(matches!(
+            other.import_mapping(&exported, &ExportFormat::Json),
+            Err(WisardError::WisardIncompatible)
+        ));
+    }
+
+    #[test]
+    fn test_dynamic_bleach() {
+        let sample = [1, 2, 3, 6, 5, 4];
+        let mut wis = Wisard::<u8>::with_params(2, 3, 0);
+        wis.train(&sample, "twice".to_string()).unwrap();
+        wis.train(&sample, "twice".to_string()).unwrap();
+        wis.train(&sample, "once".to_string()).unwrap();
+
+        let prediction = wis.classify_with_scores(&sample).unwrap();
+        assert_eq!(prediction.bleach, 0);
+        assert_eq!(prediction.margin, 0.0);
+
+        wis.set_bleach_policy(BleachPolicy::Dynamic);
+        let prediction = wis.classify_with_scores(&sample).unwrap();
+        assert_eq!(prediction.label, "twice");
+        assert_eq!(prediction.bleach, 1);
+        assert_eq!(prediction.margin, 1.0);
+
+        // raising the bleach any further silences every class, so it stays
+        let mut wis = Wisard::<u8>::with_params(2, 3, 0);
+        wis.set_bleach_policy(BleachPolicy::Dynamic);
+        wis.train(&sample, "a".to_string()).unwrap();
+        wis.train(&sample, "b".to_string()).unwrap();
+        let prediction = wis.classify_with_scores(&sample).unwrap();
+        assert_eq!(prediction.bleach, 0);
+        assert_eq!(prediction.scores[0].votes, 2);
+    }
+
+    #[test]
+    fn test_tune_bleach() {
+        let sample = vec![1, 2, 3, 6, 5, 4];
+        let other = vec![3, 2, 1, 4, 5, 6];
+        let mut wis = Wisard::<u8>::with_params(2, 3, 0);
+        assert!(matches!(
+            wis.tune_bleach(std::slice::from_ref(&sample), &["twice".to_string()]),
+            Err(WisardError::WisardEmptyModel)
+        ));
+        wis.train(&sample, "twice".to_string()).unwrap();
+        wis.train(&sample, "twice".to_string()).unwrap();
+        wis.train(&sample, "once".to_string()).unwrap();
+        wis.train(&other, "once".to_string()).unwrap();
+        wis.train(&other, "once".to_string()).unwrap();
+
+        // at 0 the first sample ties, at 2 nothing votes for either
+        let bleach = wis
+            .tune_bleach(
+                &[sample.clone(), other],
+                &["twice".to_string(), "once".to_string()],
+            )
+            .unwrap();
+        assert_eq!(bleach, 1);
+        assert_eq!(wis.get_info().2, 1);
+        assert_eq!(wis.classify(&sample).unwrap(), "twice");
+
+        assert!(matches!(
+            wis.tune_bleach(&[sample], &[]),
+            Err(WisardError::WisardLengthMismatch {
+                expected: 1,
+                actual: 0
+            })
+        ));
+    }
+
+    #[test]
+    fn test_tune_bleach_normalized() {
+        let sample = vec![1, 2, 3, 6, 5, 4];
+        let other = vec![3, 2, 1, 4, 5, 6];
+        let mut wis = Wisard::<u8>::with_params(2, 3, 0);
+        wis.train(&sample, "a".to_string()).unwrap();
+        for _ in 0..4 {
+            wis.train(&other, "a".to_string()).unwrap();
+        }
+        wis.train(&sample, "b".to_string()).unwrap();
+        wis.train(&sample, "b".to_string()).unwrap();
+        let data = [sample.clone(), other];
+        let labels = ["b".to_string(), "a".to_string()];
+
+        // by raw votes the first sample ties at 0 and needs a bleach of 1
+        assert_eq!(wis.tune_bleach(&data, &labels).unwrap(), 1);
+        // scaled by the prior, b already wins it at 0
+        wis.set_normalization(Normalization::Prior);
+        assert_eq!(wis.tune_bleach(&data, &labels).unwrap(), 0);
+        assert_eq!(wis.classify(&sample).unwrap(), "b");
+    }
+
+    #[test]
+    fn test_tie_breakers() {
+        let sample = [1, 2, 3, 6, 5, 4];
+        let mut wis = Wisard::<u8>::with_params(2, 3, 0);
+        for (label, times) in [("b", 1), ("c", 3), ("a", 2)].iter() {
+            for _ in 0..*times {
+                wis.train(&sample, label.to_string()).unwrap();
+            }
+        }
+
+        // every class has every vote, whatever order the hashmap is in
+        assert_eq!(wis.get_tie_breaker(), &TieBreaker::Label);
+        assert_eq!(wis.classify(&sample).unwrap(), "a");
+        wis.set_tie_breaker(TieBreaker::MostTrained);
+        assert_eq!(wis.classify(&sample).unwrap(), "c");
+        wis.set_tie_breaker(TieBreaker::LeastTrained);
+        assert_eq!(wis.classify(&sample).unwrap(), "b");
+
+        wis.set_tie_breaker(TieBreaker::Random(5));
+        let label = wis.classify(&sample).unwrap();
+        for _ in 0..10 {
+            assert_eq!(wis.classify(&sample).unwrap(), label);
+        }
+        let prediction = wis.classify_with_scores(&sample).unwrap();
+        assert_eq!(prediction.outcome, Outcome::Label(label.clone()));
+        assert_eq!(prediction.label, label);
+
+        wis.set_tie_breaker(TieBreaker::Ambiguous);
+        let prediction = wis.classify_with_scores(&sample).unwrap();
+        assert_eq!(
+            prediction.outcome,
+            Outcome::Ambiguous(vec!["a".to_string(), "b".to_string(), "c".to_string()])
+        );
+        assert!(matches!(
+            wis.classify(&sample),
+            Err(WisardError::WisardAmbiguous(3))
+        ));
+        // a tie doesn't fail the rest of a batch
+        wis.train(&[3, 2, 1, 4, 5, 6], "d".to_string()).unwrap();
+        let outcomes: Vec<Outcome> = wis
+            .classify_batch(&[sample.to_vec(), vec![3, 2, 1, 4, 5, 6]])
+            .unwrap()
+            .into_iter()
+            .map(|p| p.outcome)
+            .collect();
+        assert!(matches!(outcomes[0], Outcome::Ambiguous(_)));
+        assert_eq!(outcomes[1], Outcome::Label("d".to_string()));
+
+        // the tie breaker is saved with the model
+        let mut loaded = Wisard::<u8>::new();
+        loaded.load(&wis.save().unwrap()).unwrap();
+        assert_eq!(loaded.get_tie_breaker(), &TieBreaker::Ambiguous);
+    }
+
+    #[test]
+    fn test_normalization() {
+        let sample = [1, 2, 3, 6, 5, 4];
+        let other = [3, 2, 1, 4, 5, 6];
+        let mut wis = Wisard::<u8>::with_params(2, 3, 0);
+        for _ in 0..3 {
+            wis.train(&sample, "big".to_string()).unwrap();
+        }
+        wis.train(&other, "big".to_string()).unwrap();
+        wis.train(&sample, "small".to_string()).unwrap();
+
+        // both classes have every vote, the label breaks the tie
+        assert_eq!(wis.get_normalization(), &Normalization::None);
+        assert_eq!(wis.classify(&sample).unwrap(), "big");
+
+        wis.set_normalization(Normalization::Prior);
+        let prediction = wis.classify_with_scores(&sample).unwrap();
+        assert_eq!(prediction.label, "small");
+        assert_eq!(prediction.scores[0].score, 5.0 / 2.0);
+        assert_eq!(prediction.scores[1].score, 5.0 / 8.0);
+
+        wis.set_normalization(Normalization::TimesTrained);
+        assert_eq!(wis.classify(&sample).unwrap(), "small");
+
+        // big saw two addresses in four trainings, small one in one
+        wis.set_normalization(Normalization::Fill);
+        let prediction = wis.classify_with_scores(&sample).unwrap();
+        assert_eq!(prediction.label, "big");
+        assert_eq!(prediction.scores[0].score, 2.0);
+    }
+
+    #[test]
+    fn test_class_weights() {
+        let sample = [1, 2, 3, 6, 5, 4];
+        let mut wis = Wisard::<u8>::with_params(2, 3, 0);
+        wis.train(&sample, "a".to_string()).unwrap();
+        wis.train(&sample, "b".to_string()).unwrap();
+        assert_eq!(wis.get_class_weight(&"b".to_string()), 1.0);
+        assert_eq!(wis.classify(&sample).unwrap(), "a");
+
+        wis.set_class_weight("b".to_string(), 1.5).unwrap();
+        let prediction = wis.classify_with_scores(&sample).unwrap();
+        assert_eq!(prediction.label, "b");
+        assert_eq!(prediction.scores[0].score, 1.5);
+        assert_eq!(prediction.margin, 0.5);
+
+        for weight in [-1.0, f64::NAN, f64::INFINITY].iter() {
+            assert!(matches!(
+                wis.set_class_weight("a".to_string(), *weight),
+                Err(WisardError::WisardInvalidConfig(_))
+            ));
+        }
+
+        // weights and normalization are saved with the model
+        wis.set_normalization(Normalization::Prior);
+        let mut loaded = Wisard::<u8>::new();
+        loaded.load(&wis.save().unwrap()).unwrap();
+        assert_eq!(loaded.get_class_weight(&"b".to_string()), 1.5);
+        assert_eq!(loaded.get_normalization(), &Normalization::Prior);
+        let document = wis.to_document();
+        assert_eq!(document.class_weights, vec![("b".to_string(), 1.5)]);
+
+        let mut weights = HashMap::new();
+        weights.insert("a".to_string(), 3.0);
+        weights.insert("b".to_string(), -3.0);
+        assert!(wis.set_class_weights(weights.clone()).is_err());
+        assert_eq!(wis.get_class_weight(&"b".to_string()), 1.5);
+        weights.insert("b".to_string(), 0.0);
+        wis.set_class_weights(weights).unwrap();
+        assert_eq!(wis.get_class_weight(&"a".to_string()), 3.0);
+
+        wis.clear_class_weights();
+        assert_eq!(wis.classify(&sample).unwrap(), "a");
+    }
+
+    #[test]
+    fn test_reject_rule() {
+        let config = WisardConfig::new()
+            .input_length(6)
+            .addr_length(3)
+            .mapping(MappingStrategy::Contiguous);
+        let mut wis = Wisard::<u8>::with_config(&config).unwrap();
+        wis.train(&[1, 2, 3, 6, 5, 4], "a".to_string()).unwrap();
+        wis.train(&[3, 2, 1, 4, 5, 6], "b".to_string()).unwrap();
+        let sure = [1, 2, 3, 6, 5, 4];
+        // each class matches one tuple
+        let unsure = [1, 2, 3, 4, 5, 6];
+
+        assert_eq!(wis.get_reject_rule(), &RejectRule::default());
+        assert_eq!(wis.classify(&unsure).unwrap(), "a");
+
+        wis.set_reject_rule(RejectRule {
+            min_votes: 2,
+            min_margin: 0.0,
+        })
+        .unwrap();
+        assert_eq!(wis.classify(&sure).unwrap(), "a");
+        let prediction = wis.classify_with_scores(&unsure).unwrap();
+        assert_eq!(prediction.outcome, Outcome::Rejected);
+        assert_eq!(prediction.label, "a");
+        assert!(matches!(
+            wis.classify(&unsure),
+            Err(WisardError::WisardRejected)
+        ));
+
+        wis.set_reject_rule(RejectRule {
+            min_votes: 0,
+            min_margin: 0.5,
+        })
+        .unwrap();
+        assert_eq!(wis.classify(&sure).unwrap(), "a");
+        assert!(wis.classify(&unsure).is_err());
+        // a rejection doesn't fail the rest of a batch
+        let outcomes: Vec<Outcome> = wis
+            .classify_batch(&[unsure.to_vec(), sure.to_vec()])
+            .unwrap()
+            .into_iter()
+            .map(|p| p.outcome)
+            .collect();
+        assert_eq!(
+            outcomes,
+            vec![Outcome::Rejected, Outcome::Label("a".to_string())]
+        );
+        assert!(matches!(
+            wis.set_reject_rule(RejectRule {
+                min_votes: 0,
+                min_margin: 1.5,
+            }),
+            Err(WisardError::WisardInvalidConfig(_))
+        ));
+
+        let mut loaded = Wisard::<u8>::new();
+        loaded.load(&wis.save().unwrap()).unwrap();
+        assert_eq!(loaded.get_reject_rule().min_margin, 0.5);
+
+        let config = WisardConfig::from_toml("reject = { min_votes = 2 }").unwrap();
+        assert_eq!(config.get_reject_rule().min_votes, 2);
+        let config = config.reject(RejectRule {
+            min_votes: 0,
+            min_margin: -1.0,
+        });
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_top_k() {
+        let sample = [1, 2, 3, 6, 5, 4];
+        let other = [3, 2, 1, 4, 5, 6];
+        let mut wis = Wisard::<u8>::with_params(2, 3, 0);
+        assert!(matches!(
+            wis.classify_top_k(&sample, 2),
+            Err(WisardError::WisardEmptyModel)
+        ));
+        wis.train(&sample, "c".to_string()).unwrap();
+        wis.train(&sample, "a".to_string()).unwrap();
+        wis.train(&other, "b".to_string()).unwrap();
+
+        let top = wis.classify_top_k(&sample, 2).unwrap();
+        let labels: Vec<&str> = top.iter().map(|s| s.label.as_str()).collect();
+        assert_eq!(labels, vec!["a", "c"]);
+        assert_eq!(top[0].score, 1.0);
+        assert_eq!(wis.classify_top_k(&sample, 10).unwrap().len(), 3);
+        assert!(wis.classify_top_k(&sample, 0).unwrap().is_empty());
+
+        // a rejected sample still gets its candidates
+        wis.set_reject_rule(RejectRule {
+            min_votes: 3,
+            min_margin: 0.0,
+        })
+        .unwrap();
+        assert!(wis.classify(&sample).is_err());
+        assert_eq!(wis.classify_top_k(&sample, 1).unwrap()[0].label, "a");
+    }
+
+    #[test]
+    fn test_multi_label() {
+        let config = WisardConfig::new()
+            .input_length(6)
+            .addr_length(3)
+            .mapping(MappingStrategy::Contiguous);
+        let mut wis = Wisard::<u8>::with_config(&config).unwrap();
+        let cat = [1, 2, 3, 6, 5, 4];
+        let dog = [3, 2, 1, 4, 5, 6];
+        let both = [1, 2, 3, 4, 5, 6];
+        let labels =
+            |tags: &[&str]| -> Vec<String> { tags.iter().map(|t| t.to_string()).collect() };
+        wis.train_multi(&cat, &labels(&["cat", "animal", "cat"]))
+            .unwrap();
+        wis.train_multi(&dog, &labels(&["dog", "animal"])).unwrap();
+        assert_eq!(wis.discs[&"cat".to_string()].get_times_trained(), 1);
+
+        assert_eq!(
+            wis.get_label_threshold(&"cat".to_string()),
+            DEFAULT_LABEL_THRESHOLD
+        );
+        assert_eq!(
+            wis.classify_multi(&cat).unwrap(),
+            labels(&["animal", "cat"])
+        );
+        // half of the RAMs of cat and dog vote for both, which isn't above 0.5
+        assert_eq!(wis.classify_multi(&both).unwrap(), labels(&["animal"]));
+
+        wis.set_label_threshold("dog".to_string(), 0.25).unwrap();
+        assert_eq!(
+            wis.classify_multi(&both).unwrap(),
+            labels(&["animal", "dog"])
+        );
+        assert!(wis
+            .set_label_threshold("dog".to_string(), f64::NAN)
+            .is_err());
+
+        // on held-out data both is tagged cat and dog
+        let thresholds = wis
+            .calibrate_thresholds(
+                &[cat.to_vec(), dog.to_vec(), both.to_vec()],
+                &[
+                    labels(&["cat", "animal"]),
+                    labels(&["dog", "animal"]),
+                    labels(&["cat", "dog", "animal"]),
+                ],
+            )
+            .unwrap();
+        assert_eq!(thresholds[&"cat".to_string()], 0.25);
+        assert_eq!(thresholds[&"animal".to_string()], 0.5);
+        assert_eq!(
+            wis.classify_multi(&both).unwrap(),
+            labels(&["animal", "cat", "dog"])
+        );
+        assert!(matches!(
+            wis.calibrate_thresholds(&[cat.to_vec()], &[]),
+            Err(WisardError::WisardLengthMismatch { .. })
+        ));
+
+        // a label no held-out sample is tagged with keeps its threshold
+        wis.train_multi(&both, &labels(&["bird"])).unwrap();
+        wis.set_label_threshold("bird".to_string(), 0.75).unwrap();
+        let dog_threshold = wis.get_label_threshold(&"dog".to_string());
+        let thresholds = wis
+            .calibrate_thresholds(&[cat.to_vec()], &[labels(&["cat"])])
+            .unwrap();
+        assert_eq!(thresholds.keys().collect::<Vec<_>>(), vec!["cat"]);
+        assert_eq!(wis.get_label_threshold(&"bird".to_string()), 0.75);
+        assert_eq!(wis.get_label_threshold(&"dog".to_string()), dog_threshold);
+
+        let rank_entries = wis.rank_tables.len();
+        assert!(matches!(
+            wis.train_multi(&[6, 5, 4, 3, 2, 1], &[]),
+            Err(WisardError::WisardInvalidConfig(_))
+        ));
+        assert_eq!(wis.rank_tables.len(), rank_entries);
+
+        let mut loaded = Wisard::<u8>::new();
+        loaded.load(&wis.save().unwrap()).unwrap();
+        assert_eq!(loaded.get_label_threshold(&"dog".to_string()), 0.25);
+    }
+
+    #[test]
+    fn test_best_threshold() {
+        let mut pairs = vec![(0.9, true), (0.2, false), (0.6, true), (0.4, false)];
+        assert_eq!(best_threshold(&mut pairs), 0.5);
+        // never tagged, nothing seen is above the threshold
+        let mut pairs = vec![(0.3, false), (0.7, false)];
+        assert_eq!(best_threshold(&mut pairs), 0.7);
+        let mut pairs = vec![(0.3, true), (0.3, true)];
+        assert_eq!(best_threshold(&mut pairs), 0.15);
+    }
 }
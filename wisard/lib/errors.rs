@@ -1,20 +1,157 @@
-use std::{error::Error, fmt};
+use std::{error::Error, fmt, io};
 
 #[derive(Debug)]
 pub enum WisardError {
-    WisardOutOfBounds,
-    WisardValidationFailed,
-    WisardIOError,
+    // the sample has fewer values than the mapping reads
+    WisardInputTooShort { expected: usize, actual: usize },
+    // two lists that go together, like samples and labels, differ in length
+    WisardLengthMismatch { expected: usize, actual: usize },
+    // classify was called before any training
+    WisardEmptyModel,
+    WisardUnknownLabel,
+    // untrain of a sample that was never trained with its label
+    WisardNotTrained,
+    WisardIncomparable,
+    WisardUnsupported,
+    WisardIncompatible,
+    WisardIOError(io::Error),
+    // the model couldn't be turned into bytes
+    WisardSerialization(Box<dyn Error + Send + Sync>),
+    // the bytes couldn't be turned into a model
+    WisardMalformed(Box<dyn Error + Send + Sync>),
+    // the model parsed but breaks an invariant, the reason says which
+    WisardInvalidModel(&'static str),
+    WisardUnknownFormat,
+    WisardUnsupportedVersion(u16),
+    WisardTypeMismatch { expected: String, found: String },
+    WisardChecksumMismatch,
+    WisardLimitExceeded(&'static str),
+    // a WisardConfig that can't build a model, the reason says why
+    WisardInvalidConfig(String),
+    // this many classes tie for first place and the tie breaker is Ambiguous
+    WisardAmbiguous(usize),
+    // no class is confident enough for the reject rule
+    WisardRejected,
 }
 
-impl Error for WisardError {}
+impl WisardError {
+    // stable identifier of the kind of error, for servers and clients to
+    // match on instead of the message
+    pub fn code(&self) -> &'static str {
+        match self {
+            WisardError::WisardInputTooShort { .. } => "input_too_short",
+            WisardError::WisardLengthMismatch { .. } => "length_mismatch",
+            WisardError::WisardEmptyModel => "empty_model",
+            WisardError::WisardUnknownLabel => "unknown_label",
+            WisardError::WisardNotTrained => "not_trained",
+            WisardError::WisardIncomparable => "incomparable",
+            WisardError::WisardUnsupported => "unsupported",
+            WisardError::WisardIncompatible => "incompatible",
+            WisardError::WisardIOError(_) => "io",
+            WisardError::WisardSerialization(_) => "serialization",
+            WisardError::WisardMalformed(_) => "malformed",
+            WisardError::WisardInvalidModel(_) => "invalid_model",
+            WisardError::WisardUnknownFormat => "unknown_format",
+            WisardError::WisardUnsupportedVersion(_) => "unsupported_version",
+            WisardError::WisardTypeMismatch { .. } => "type_mismatch",
+            WisardError::WisardChecksumMismatch => "checksum_mismatch",
+            WisardError::WisardLimitExceeded(_) => "limit_exceeded",
+            WisardError::WisardInvalidConfig(_) => "invalid_config",
+            WisardError::WisardAmbiguous(_) => "ambiguous",
+            WisardError::WisardRejected => "rejected",
+        }
+    }
+}
+
+impl Error for WisardError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            WisardError::WisardIOError(error) => Some(error),
+            WisardError::WisardSerialization(error) => Some(error.as_ref()),
+            WisardError::WisardMalformed(error) => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for WisardError {
+    fn from(error: io::Error) -> Self {
+        WisardError::WisardIOError(error)
+    }
+}
 
 impl fmt::Display for WisardError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            WisardError::WisardOutOfBounds => write!(f, "Aaaand we're out of bounds!"),
-            WisardError::WisardValidationFailed => write!(f, "This file is not what I expected!"),
-            WisardError::WisardIOError => write!(f, "IO Error!"),
+            WisardError::WisardInputTooShort { expected, actual } => write!(
+                f,
+                "Input has {} values, the mapping needs {}",
+                actual, expected
+            ),
+            WisardError::WisardLengthMismatch { expected, actual } => {
+                write!(f, "Expected {} items, got {}", expected, actual)
+            }
+            WisardError::WisardEmptyModel => write!(f, "The model has no classes, train it first"),
+            WisardError::WisardUnknownLabel => write!(f, "The model has no class with this label"),
+            WisardError::WisardNotTrained => {
+                write!(f, "This sample was never trained with this label")
+            }
+            WisardError::WisardIncomparable => {
+                write!(f, "Can't rank values that don't compare, like NaN")
+            }
+            WisardError::WisardUnsupported => write!(f, "The RAMs of this model can't do that"),
+            WisardError::WisardIncompatible => write!(f, "The models don't share hyperparameters"),
+            WisardError::WisardIOError(error) => write!(f, "IO error: {}", error),
+            WisardError::WisardSerialization(error) => {
+                write!(f, "Couldn't serialize the model: {}", error)
+            }
+            WisardError::WisardMalformed(error) => write!(f, "The model doesn't parse: {}", error),
+            WisardError::WisardInvalidModel(reason) => write!(f, "Invalid model: {}", reason),
+            WisardError::WisardUnknownFormat => write!(f, "This is not a model file"),
+            WisardError::WisardUnsupportedVersion(version) => {
+                write!(f, "Model file version {} is not supported", version)
+            }
+            WisardError::WisardTypeMismatch { expected, found } => {
+                write!(f, "Expected a model of {}, found {}", expected, found)
+            }
+            WisardError::WisardChecksumMismatch => {
+                write!(f, "The model file is corrupted, its checksum doesn't match")
+            }
+            WisardError::WisardLimitExceeded(limit) => {
+                write!(f, "The model goes over the limit of {}", limit)
+            }
+            WisardError::WisardInvalidConfig(reason) => {
+                write!(f, "Invalid configuration: {}", reason)
+            }
+            WisardError::WisardAmbiguous(tied) => {
+                write!(f, "{} classes tie for the best score", tied)
+            }
+            WisardError::WisardRejected => {
+                write!(f, "No class is confident enough, the sample is rejected")
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod lib_tests {
+    use super::*;
+
+    #[test]
+    fn test_source_and_code() {
+        let error = WisardError::from(io::Error::new(io::ErrorKind::NotFound, "no model"));
+        assert_eq!(error.code(), "io");
+        assert_eq!(error.source().unwrap().to_string(), "no model");
+
+        let error = WisardError::WisardInputTooShort {
+            expected: 784,
+            actual: 10,
+        };
+        assert_eq!(error.code(), "input_too_short");
+        assert!(error.source().is_none());
+        assert_eq!(
+            error.to_string(),
+            "Input has 10 values, the mapping needs 784"
+        );
+    }
+}
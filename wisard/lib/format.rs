@@ -0,0 +1,298 @@
+use crate::errors::WisardError;
+use bincode::Options;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+// a model file is MAGIC, the format version as a little endian u16, the
+// bincode of a Header and then the bincode of the model itself
+pub const MAGIC: [u8; 4] = *b"WSRD";
+// 0 is the headerless bincode written before there was a format at all, 1 the
+// dict_wisard model before it had an input length, a remainder, a mapping
+// strategy and its section of policies. New policies go in that section and
+// don't change the version
+pub const FORMAT_VERSION: u16 = 2;
+
+// the name a type is saved under. Unlike std::any::type_name it doesn't change
+// with the compiler or the module a type lives in, so files keep loading
+pub trait TypeTag {
+    const TAG: &'static str;
+}
+
+macro_rules! type_tags {
+    ($($t:ty),*) => {
+        $(impl TypeTag for $t {
+            const TAG: &'static str = stringify!($t);
+        })*
+    };
+}
+
+type_tags!(
+    u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64, char, bool, String
+);
+
+// files written before the tags hold type_name strings, read as these tags
+const LEGACY_NAMES: [(&str, &str); 1] = [("alloc::string::String", "String")];
+
+fn from_legacy(name: &str) -> &str {
+    LEGACY_NAMES
+        .iter()
+        .find(|(legacy, _)| *legacy == name)
+        .map_or(name, |(_, tag)| *tag)
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Header {
+    // which model wrote the file, "dict_wisard" or "binary_wisard"
+    pub model: String,
+    // type tags of the input elements and of the labels
+    pub element_type: String,
+    pub label_type: String,
+    pub number_of_hashtables: u16,
+    pub addr_length: u16,
+    pub bleach: u16,
+    pub body_length: u64,
+    // crc32 of the body
+    pub crc: u32,
+}
+
+impl Header {
+    pub(crate) fn new<T: TypeTag, L: TypeTag>(
+        model: &str,
+        info: (u16, u16, u16),
+        body: &[u8],
+    ) -> Header {
+        Header {
+            model: model.to_string(),
+            element_type: T::TAG.to_string(),
+            label_type: L::TAG.to_string(),
+            number_of_hashtables: info.0,
+            addr_length: info.1,
+            bleach: info.2,
+            body_length: body.len() as u64,
+            crc: crc32fast::hash(body),
+        }
+    }
+
+    // rejects files written by another model or for other types
+    pub(crate) fn expect<T: TypeTag, L: TypeTag>(&self, model: &str) -> Result<(), WisardError> {
+        expect_types::<T, L>(model, &self.model, &self.element_type, &self.label_type)
+    }
+}
+
+pub(crate) fn expect_types<T: TypeTag, L: TypeTag>(
+    model: &str,
+    found_model: &str,
+    element_type: &str,
+    label_type: &str,
+) -> Result<(), WisardError> {
+    let checks = [
+        (model, found_model),
+        (T::TAG, from_legacy(element_type)),
+        (L::TAG, from_legacy(label_type)),
+    ];
+    for (expected, found) in checks.iter() {
+        if expected != found {
+            return Err(WisardError::WisardTypeMismatch {
+                expected: expected.to_string(),
+                found: found.to_string(),
+            });
+        }
+    }
+    Ok(())
+}
+
+// formats to exchange models with people and other languages, unlike save
+// they have no header, the types are part of the document
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub enum ExportFormat {
+    Json,
+    MessagePack,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "application/json",
+            ExportFormat::MessagePack => "application/msgpack",
+        }
+    }
+
+    pub fn from_content_type(content_type: &str) -> Option<ExportFormat> {
+        let essence = content_type.split(';').next().unwrap_or("").trim();
+        match essence {
+            "application/json" => Some(ExportFormat::Json),
+            "application/msgpack" | "application/x-msgpack" => Some(ExportFormat::MessagePack),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn encode<D: Serialize>(&self, document: &D) -> Result<Vec<u8>, WisardError> {
+        match self {
+            ExportFormat::Json => serde_json::to_vec_pretty(document)
+                .map_err(|error| WisardError::WisardSerialization(Box::new(error))),
+            // field names are kept so other languages get maps, not tuples
+            ExportFormat::MessagePack => rmp_serde::to_vec_named(document)
+                .map_err(|error| WisardError::WisardSerialization(Box::new(error))),
+        }
+    }
+
+    pub(crate) fn decode<D: DeserializeOwned>(&self, stream: &[u8]) -> Result<D, WisardError> {
+        match self {
+            ExportFormat::Json => serde_json::from_slice(stream)
+                .map_err(|error| WisardError::WisardMalformed(Box::new(error))),
+            ExportFormat::MessagePack => rmp_serde::from_slice(stream)
+                .map_err(|error| WisardError::WisardMalformed(Box::new(error))),
+        }
+    }
+}
+
+// the encoding of bincode::serialize, reading at most limit bytes, so no length
+// prefix can ask for more memory than that
+pub(crate) fn options(limit: u64) -> impl Options + Copy {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(limit)
+}
+
+pub(crate) fn encode(header: &Header, body: &[u8]) -> Result<Vec<u8>, WisardError> {
+    let mut encoded = MAGIC.to_vec();
+    encoded.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    bincode::serialize_into(&mut encoded, header)
+        .map_err(|error| WisardError::WisardSerialization(Box::new(error)))?;
+    encoded.extend_from_slice(body);
+    Ok(encoded)
+}
+
+// the version of a stream, None when it has no magic and so may be a version 0
+// file
+pub fn read_version(stream: &[u8]) -> Result<Option<u16>, WisardError> {
+    if stream.len() < MAGIC.len() || stream[..MAGIC.len()] != MAGIC {
+        return Ok(None);
+    }
+    match stream.get(MAGIC.len()..MAGIC.len() + 2) {
+        Some(version) => Ok(Some(u16::from_le_bytes([version[0], version[1]]))),
+        None => Err(WisardError::WisardUnknownFormat),
+    }
+}
+
+// the header of a versioned stream and its body, once the body is checked
+// against the length and crc of the header
+pub fn read_header(stream: &[u8]) -> Result<(Header, &[u8]), WisardError> {
+    read_header_with_limit(stream, u64::MAX)
+}
+
+// same as read_header, for streams of at most max_bytes
+pub fn read_header_with_limit(
+    stream: &[u8],
+    max_bytes: u64,
+) -> Result<(Header, &[u8]), WisardError> {
+    if stream.len() as u64 > max_bytes {
+        return Err(WisardError::WisardLimitExceeded("bytes"));
+    }
+    match read_version(stream)? {
+        None => return Err(WisardError::WisardUnknownFormat),
+        Some(version) if version > FORMAT_VERSION || version == 0 => {
+            return Err(WisardError::WisardUnsupportedVersion(version))
+        }
+        Some(_) => (),
+    }
+    let rest = &stream[MAGIC.len() + 2..];
+    let options = options(max_bytes);
+    let header: Header = options
+        .deserialize(rest)
+        .map_err(|_| WisardError::WisardUnknownFormat)?;
+    let header_length = options
+        .serialized_size(&header)
+        .map_err(|_| WisardError::WisardUnknownFormat)?;
+    let rest = &rest[header_length as usize..];
+    if rest.len() as u64 != header.body_length || crc32fast::hash(rest) != header.crc {
+        return Err(WisardError::WisardChecksumMismatch);
+    }
+    Ok((header, rest))
+}
+
+#[cfg(test)]
+mod lib_tests {
+    use super::*;
+
+    #[test]
+    fn test_header_roundtrip() {
+        let body = vec![1, 2, 3, 4];
+        let header = Header::new::<u8, String>("dict_wisard", (28, 28, 0), &body);
+        let encoded = encode(&header, &body).unwrap();
+        assert_eq!(read_version(&encoded).unwrap(), Some(FORMAT_VERSION));
+
+        let (decoded, decoded_body) = read_header(&encoded).unwrap();
+        assert_eq!(decoded, header);
+        assert_eq!(decoded_body, &body[..]);
+        assert!(decoded.expect::<u8, String>("dict_wisard").is_ok());
+        assert!(matches!(
+            decoded.expect::<u16, String>("dict_wisard"),
+            Err(WisardError::WisardTypeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_type_tags() {
+        let header = Header::new::<f32, String>("dict_wisard", (28, 28, 0), &[]);
+        assert_eq!(header.element_type, "f32");
+        assert_eq!(header.label_type, "String");
+
+        // files from before the tags have the type_name of the labels
+        let legacy = Header {
+            label_type: "alloc::string::String".to_string(),
+            ..header.clone()
+        };
+        assert!(legacy.expect::<f32, String>("dict_wisard").is_ok());
+        let renamed = Header {
+            label_type: "my_crate::Label".to_string(),
+            ..header
+        };
+        assert!(matches!(
+            renamed.expect::<f32, String>("dict_wisard"),
+            Err(WisardError::WisardTypeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_header_rejections() {
+        let body = vec![1, 2, 3, 4];
+        let header = Header::new::<u8, String>("dict_wisard", (28, 28, 0), &body);
+        let mut encoded = encode(&header, &body).unwrap();
+
+        let last = encoded.len() - 1;
+        encoded[last] ^= 1;
+        assert!(matches!(
+            read_header(&encoded),
+            Err(WisardError::WisardChecksumMismatch)
+        ));
+        assert!(matches!(
+            read_header(&encoded[..last]),
+            Err(WisardError::WisardChecksumMismatch)
+        ));
+
+        encoded[4] = 9;
+        assert!(matches!(
+            read_header(&encoded),
+            Err(WisardError::WisardUnsupportedVersion(9))
+        ));
+        assert!(matches!(
+            read_header(b"not a model"),
+            Err(WisardError::WisardUnknownFormat)
+        ));
+
+        // a string length prefix far past the end of the stream
+        let mut huge = MAGIC.to_vec();
+        huge.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        huge.extend_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(
+            read_header_with_limit(&huge, 1024),
+            Err(WisardError::WisardUnknownFormat)
+        ));
+        assert!(matches!(
+            read_header_with_limit(&huge, 8),
+            Err(WisardError::WisardLimitExceeded("bytes"))
+        ));
+    }
+}
@@ -1,2 +1,7 @@
+pub mod binary_wisard;
+pub mod bloom;
+pub mod config;
 pub mod dict_wisard;
 pub mod errors;
+pub mod format;
+pub mod mapping;
@@ -0,0 +1,303 @@
+use crate::dict_wisard::RemainderPolicy;
+use crate::errors::WisardError;
+use rand::seq::SliceRandom;
+use rand::{thread_rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+// how the inputs are spread over the RAMs
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub enum MappingStrategy {
+    // a shuffle of every input, from the seed when there is one
+    Random,
+    // the inputs in order, each tuple reads a run of neighbours
+    Contiguous,
+    // an image of width × height, row after row, cut into patches that are
+    // shuffled on their own, so a tuple reads from one region of the image
+    Patches {
+        width: usize,
+        height: usize,
+        patch_width: usize,
+        patch_height: usize,
+    },
+    // with n hashtables, tuple i reads inputs i, i + n, i + 2n... Only the
+    // last stripe can be shorter than a tuple, other sizes are rejected
+    // rather than having tuples that straddle two stripes
+    Striped,
+    // the mapping as given, positions past the input length being padding
+    Custom(Vec<u64>),
+}
+
+impl MappingStrategy {
+    // the mapping of a model with these sizes
+    pub(crate) fn mapping(
+        &self,
+        input_length: usize,
+        number_of_hashtables: usize,
+        addr_length: usize,
+        remainder: &RemainderPolicy,
+        seed: Option<u64>,
+    ) -> Vec<u64> {
+        let order = match self {
+            MappingStrategy::Random => shuffled(input_length, seed),
+            MappingStrategy::Contiguous => (0..input_length as u64).collect(),
+            MappingStrategy::Patches {
+                width,
+                height,
+                patch_width,
+                patch_height,
+            } => patches(*width, *height, *patch_width, *patch_height, seed),
+            MappingStrategy::Striped => (0..number_of_hashtables)
+                .flat_map(|i| (i..input_length).step_by(number_of_hashtables))
+                .map(|i| i as u64)
+                .collect(),
+            MappingStrategy::Custom(mapping) => return mapping.clone(),
+        };
+        let mapping_length =
+            remainder.mapping_length(input_length, number_of_hashtables, addr_length);
+        match remainder {
+            // padding goes in the last tuple
+            RemainderPolicy::Pad => order
+                .into_iter()
+                .chain(input_length as u64..mapping_length as u64)
+                .collect(),
+            _ => order.iter().cycle().take(mapping_length).copied().collect(),
+        }
+    }
+
+    // why the strategy can't map input_length inputs over tuples of that
+    // size, if it can't
+    pub(crate) fn check(
+        &self,
+        input_length: usize,
+        number_of_hashtables: usize,
+        addr_length: usize,
+    ) -> Result<(), &'static str> {
+        match self {
+            MappingStrategy::Patches {
+                width,
+                height,
+                patch_width,
+                patch_height,
+            } => {
+                if *patch_width == 0 || *patch_height == 0 {
+                    return Err("a patch has no pixels");
+                }
+                if width.checked_mul(*height) != Some(input_length) {
+                    return Err("the image doesn't have as many pixels as the input");
+                }
+                if width % patch_width != 0 || height % patch_height != 0 {
+                    return Err("the patches don't tile the image");
+                }
+                match patch_width.checked_mul(*patch_height) {
+                    Some(pixels) if addr_length > 0 && pixels % addr_length == 0 => Ok(()),
+                    _ => Err("a patch doesn't split into whole tuples"),
+                }
+            }
+            MappingStrategy::Striped => {
+                let n = number_of_hashtables;
+                // stripe i holds the inputs i, i + n... below input_length
+                let stripe = |i: usize| match input_length.checked_sub(i + 1) {
+                    Some(last) => last / n + 1,
+                    None => 0,
+                };
+                if n > 1 && (0..n - 1).any(|i| stripe(i) != addr_length) {
+                    return Err("the stripes don't line up with the tuples");
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// randomizes the mapping, a seed makes it the same on every run
+pub(crate) fn shuffled(length: usize, seed: Option<u64>) -> Vec<u64> {
+    let mut mapping = (0..length as u64).collect::<Vec<u64>>();
+    match seed {
+        Some(seed) => mapping.shuffle(&mut ChaCha8Rng::seed_from_u64(seed)),
+        None => mapping.shuffle(&mut thread_rng()),
+    }
+    mapping
+}
+
+// the pixels of every patch, patches left to right and top to bottom, with the
+// patches on the right and bottom edges cut to the image
+fn patches(
+    width: usize,
+    height: usize,
+    patch_width: usize,
+    patch_height: usize,
+    seed: Option<u64>,
+) -> Vec<u64> {
+    let mut rng = match seed {
+        Some(seed) => ChaCha8Rng::seed_from_u64(seed),
+        None => ChaCha8Rng::from_rng(thread_rng()).expect("thread_rng doesn't fail"),
+    };
+    let mut order = Vec::with_capacity(width * height);
+    for top in (0..height).step_by(patch_height) {
+        for left in (0..width).step_by(patch_width) {
+            let start = order.len();
+            for y in top..height.min(top + patch_height) {
+                for x in left..width.min(left + patch_width) {
+                    order.push((y * width + x) as u64);
+                }
+            }
+            order[start..].shuffle(&mut rng);
+        }
+    }
+    order
+}
+
+// a mapping on its own, to share it between models of the same sizes
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct MappingDocument {
+    pub input_length: usize,
+    pub addr_length: u16,
+    pub number_of_hashtables: u16,
+    pub remainder: RemainderPolicy,
+    pub mapping: Vec<u64>,
+}
+
+// why a mapping can't be used by a model with these sizes, if it can't
+pub(crate) fn check_mapping(
+    mapping: &[u64],
+    input_length: usize,
+    number_of_hashtables: usize,
+    addr_length: usize,
+    remainder: &RemainderPolicy,
+) -> Result<(), WisardError> {
+    let mapping_length = remainder.mapping_length(input_length, number_of_hashtables, addr_length);
+    if remainder.number_of_hashtables(input_length, addr_length) != number_of_hashtables
+        || mapping.len() != mapping_length
+    {
+        return Err(WisardError::WisardInvalidModel(
+            "the hashtables don't match the input length",
+        ));
+    }
+    let fits = match remainder {
+        RemainderPolicy::Pad => is_permutation(mapping, mapping_length),
+        RemainderPolicy::Wrap => mapping.iter().all(|&i| (i as usize) < input_length),
+        RemainderPolicy::Drop | RemainderPolicy::Shorter => {
+            are_distinct_below(mapping, input_length)
+        }
+    };
+    if !fits {
+        return Err(WisardError::WisardInvalidModel(
+            "the mapping is not a permutation of the inputs",
+        ));
+    }
+    Ok(())
+}
+
+pub(crate) fn is_permutation(values: &[u64], length: usize) -> bool {
+    values.len() == length && are_distinct_below(values, length)
+}
+
+fn are_distinct_below(values: &[u64], length: usize) -> bool {
+    let mut seen = vec![false; length];
+    for &v in values {
+        match seen.get_mut(v as usize) {
+            Some(s) if !*s => *s = true,
+            _ => return false,
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod lib_tests {
+    use super::*;
+
+    #[test]
+    fn test_strategies() {
+        let drop = RemainderPolicy::Drop;
+        assert_eq!(
+            MappingStrategy::Contiguous.mapping(6, 2, 3, &drop, None),
+            vec![0, 1, 2, 3, 4, 5]
+        );
+        assert_eq!(
+            MappingStrategy::Striped.mapping(6, 2, 3, &drop, None),
+            vec![0, 2, 4, 1, 3, 5]
+        );
+        assert_eq!(
+            MappingStrategy::Contiguous.mapping(5, 2, 3, &RemainderPolicy::Pad, None),
+            vec![0, 1, 2, 3, 4, 5]
+        );
+        assert_eq!(
+            MappingStrategy::Contiguous.mapping(5, 2, 3, &RemainderPolicy::Wrap, None),
+            vec![0, 1, 2, 3, 4, 0]
+        );
+        assert_eq!(
+            MappingStrategy::Random.mapping(6, 2, 3, &drop, Some(4)),
+            MappingStrategy::Random.mapping(6, 2, 3, &drop, Some(4))
+        );
+
+        // a 4x4 image in 2x2 patches, each tuple reads one patch
+        let patches = MappingStrategy::Patches {
+            width: 4,
+            height: 4,
+            patch_width: 2,
+            patch_height: 2,
+        };
+        let mapping = patches.mapping(16, 4, 4, &drop, Some(1));
+        let mut tuples: Vec<Vec<u64>> = mapping
+            .chunks(4)
+            .map(|tuple| {
+                let mut tuple = tuple.to_vec();
+                tuple.sort_unstable();
+                tuple
+            })
+            .collect();
+        tuples.sort();
+        assert_eq!(
+            tuples,
+            vec![
+                vec![0, 1, 4, 5],
+                vec![2, 3, 6, 7],
+                vec![8, 9, 12, 13],
+                vec![10, 11, 14, 15]
+            ]
+        );
+        assert!(patches.check(16, 4, 4).is_ok());
+        assert!(patches.check(15, 4, 4).is_err());
+        assert!(patches.check(16, 8, 2).is_ok());
+        // a tuple of 3 would read from two patches
+        assert_eq!(
+            patches.check(16, 6, 3),
+            Err("a patch doesn't split into whole tuples")
+        );
+        let untiled = MappingStrategy::Patches {
+            width: 5,
+            height: 4,
+            patch_width: 2,
+            patch_height: 2,
+        };
+        assert_eq!(
+            untiled.check(20, 5, 4),
+            Err("the patches don't tile the image")
+        );
+
+        // only the last stripe may be short
+        let striped = MappingStrategy::Striped;
+        assert!(striped.check(6, 2, 3).is_ok());
+        assert!(striped.check(5, 2, 3).is_ok());
+        assert!(striped.check(7, 1, 3).is_ok());
+        assert_eq!(
+            striped.check(7, 3, 3),
+            Err("the stripes don't line up with the tuples")
+        );
+        assert!(striped.check(7, 2, 3).is_err());
+    }
+
+    #[test]
+    fn test_check_mapping() {
+        let drop = RemainderPolicy::Drop;
+        assert!(check_mapping(&[2, 0, 1, 3], 5, 2, 2, &drop).is_ok());
+        assert!(check_mapping(&[2, 0, 1, 1], 5, 2, 2, &drop).is_err());
+        assert!(check_mapping(&[2, 0, 1, 5], 5, 2, 2, &drop).is_err());
+        assert!(check_mapping(&[2, 0, 1], 5, 2, 2, &drop).is_err());
+        assert!(check_mapping(&[5, 0, 1, 4, 2, 3], 5, 3, 2, &RemainderPolicy::Pad).is_ok());
+        assert!(check_mapping(&[4, 0, 1, 4, 2, 3], 5, 3, 2, &RemainderPolicy::Wrap).is_ok());
+    }
+}